@@ -7,14 +7,21 @@ use reqwest::header::{
 };
 use std::collections::HashMap;
 
+use guard::DiskGuard;
+use stats::Stats;
 use std::io::Read;
 use std::str::FromStr;
 use std::thread::sleep;
 use std::time::Duration;
+use tracing::{debug, info, warn};
 use util::Segment;
 
+pub mod guard;
 mod hls;
 pub mod httpflv;
+pub mod recovery;
+pub mod schedule;
+pub mod stats;
 pub mod util;
 
 pub fn download(
@@ -22,10 +29,21 @@ pub fn download(
     headers: HeaderMap,
     file_name: &str,
     segment: Segment,
+    stats: &Stats,
+    guard: DiskGuard,
 ) -> anyhow::Result<()> {
-    let mut response = get_response(url, &headers)?;
+    let _span = tracing::info_span!("recording", url, file = file_name).entered();
+    // Offline streams answer 404 or close the connection without any data.
+    let ended = || crate::error::Error::StreamEnded(url.to_string());
+    let mut response = get_response(url, &headers).map_err(|e| match e.status() {
+        Some(reqwest::StatusCode::NOT_FOUND) => ended(),
+        _ => e.into(),
+    })?;
     let buf = &mut [0u8; 9];
-    response.read_exact(buf)?;
+    response.read_exact(buf).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => ended(),
+        _ => e.into(),
+    })?;
     // let out = File::create(format!("{}.flv", file_name)).expect("Unable to create file.");
     // let mut writer = BufWriter::new(out);
     // let mut buf = [0u8; 8 * 1024];
@@ -33,18 +51,20 @@ pub fn download(
     // io::copy(&mut resp, &mut out).expect("Unable to copy the content.");
     match header(buf) {
         Ok((_i, header)) => {
-            println!("header: {header:#?}");
-            println!("{}", response.status());
+            debug!(status = %response.status(), ?header, "Received FLV header");
             let connection = Connection::new(response);
-            println!("Downloading {}...", url);
-            httpflv::download(connection, file_name, segment);
+            info!("Downloading FLV stream");
+            httpflv::download(connection, file_name, segment, stats, guard)?;
         }
         Err(Err::Incomplete(needed)) => {
-            println!("needed: {needed:?}")
+            warn!(?needed, "Incomplete FLV header")
         }
         Err(e) => {
-            println!("{e}");
-            hls::download(url, &headers, file_name, segment)?;
+            debug!(error = %e, "Not an FLV stream, trying HLS");
+            match hls::download(url, &headers, file_name, segment, stats, guard) {
+                Err(crate::error::Error::Stopped) => info!("Recording stopped"),
+                result => result?,
+            }
         }
     }
     Ok(())
@@ -83,9 +103,11 @@ fn retry<O, E: std::fmt::Display>(mut f: impl FnMut() -> Result<O, E>) -> Result
         match f() {
             Err(e) if retries < 3 => {
                 retries += 1;
-                println!(
-                    "Retry attempt #{}. Sleeping {wait}s before the next attempt. {e}",
-                    retries,
+                warn!(
+                    attempt = retries,
+                    wait_secs = wait,
+                    error = %e,
+                    "Request failed, retrying"
                 );
                 sleep(Duration::from_secs(wait));
                 wait *= 2;
@@ -98,11 +120,13 @@ fn retry<O, E: std::fmt::Display>(mut f: impl FnMut() -> Result<O, E>) -> Result
 #[cfg(test)]
 mod tests {
     use crate::downloader::download;
+    use crate::downloader::stats::Stats;
     use crate::downloader::util::Segment;
     use anyhow::Result;
     use reqwest::header::{HeaderMap, HeaderValue, REFERER};
 
     #[test]
+    #[ignore = "needs a live stream url"]
     fn it_works() -> Result<()> {
         tracing_subscriber::fmt::init();
 
@@ -117,6 +141,8 @@ mod tests {
             "testdouyu%Y-%m-%dT%H_%M_%S",
             // Segment::Size(20 * 1024 * 1024, 0),
             Segment::Time(std::time::Duration::from_secs(6000), Default::default()),
+            &Stats::new(),
+            Default::default(),
         )?;
         Ok(())
     }
@@ -0,0 +1,174 @@
+use crate::downloader::stats::Stats;
+use crate::error::{Error, Result};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// What to do once a recording exceeds its [`Quota`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuotaPolicy {
+    /// Delete the oldest completed segments of the recording until it fits again.
+    DeleteOldest,
+    /// Stop the recording and finalize the current file.
+    Stop,
+}
+
+#[derive(Clone, Debug)]
+pub struct Quota {
+    /// Maximum bytes kept on disk for one recording.
+    pub max_bytes: u64,
+    pub policy: QuotaPolicy,
+}
+
+/// Stops a recording before the output volume runs full.
+///
+/// Free space is checked on every segment rotation and at most every `check_interval`
+/// otherwise, while a [`Stats::stop`] request is honored on every check. Returning an error
+/// from [`DiskGuard::check`] lets the downloader unwind, which finalizes the current file
+/// instead of failing in the middle of a tag.
+#[derive(Clone, Debug)]
+pub struct DiskGuard {
+    /// Stop once the free space on the output volume drops below this many bytes, 0 disables it.
+    pub min_free_space: u64,
+    pub check_interval: Duration,
+    pub quota: Option<Quota>,
+    last_check: Option<Instant>,
+    completed: VecDeque<PathBuf>,
+}
+
+impl DiskGuard {
+    pub fn new(min_free_space: u64, check_interval: Duration, quota: Option<Quota>) -> Self {
+        Self {
+            min_free_space,
+            check_interval,
+            quota,
+            last_check: None,
+            completed: VecDeque::new(),
+        }
+    }
+
+    /// Registers a finished segment, e.g. `name.flv`, and checks the limits right away.
+    pub fn rotated(&mut self, finished: PathBuf, stats: &Stats) -> Result<()> {
+        self.completed.push_back(finished);
+        self.last_check = None;
+        self.check(stats)
+    }
+
+    pub fn check(&mut self, stats: &Stats) -> Result<()> {
+        if stats.stopped() {
+            return Err(Error::Stopped);
+        }
+        if matches!(self.last_check, Some(last) if last.elapsed() < self.check_interval) {
+            return Ok(());
+        }
+        self.last_check = Some(Instant::now());
+        let snapshot = stats.snapshot();
+        let current = match &snapshot.file_name {
+            Some(file_name) => Path::new(file_name),
+            None => return Ok(()),
+        };
+        if self.min_free_space > 0 {
+            let dir = current
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."));
+            let available = fs2::available_space(dir)?;
+            if available < self.min_free_space {
+                return Err(Error::LowDiskSpace {
+                    available,
+                    threshold: self.min_free_space,
+                });
+            }
+        }
+        if let Some(quota) = &self.quota {
+            let mut used = snapshot.file_bytes + self.completed_size();
+            while used > quota.max_bytes {
+                match (quota.policy, self.completed.pop_front()) {
+                    (QuotaPolicy::DeleteOldest, Some(oldest)) => {
+                        let size = std::fs::metadata(&oldest).map_or(0, |m| m.len());
+                        match std::fs::remove_file(&oldest) {
+                            Ok(()) => info!("Quota exceeded, deleted {}", oldest.display()),
+                            Err(e) => warn!("Unable to delete {}: {e}", oldest.display()),
+                        }
+                        used = used.saturating_sub(size);
+                    }
+                    _ => return Err(Error::QuotaExceeded(quota.max_bytes)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn completed_size(&self) -> u64 {
+        self.completed
+            .iter()
+            .filter_map(|path| std::fs::metadata(path).ok())
+            .map(|m| m.len())
+            .sum()
+    }
+}
+
+impl Default for DiskGuard {
+    /// Never stops a recording.
+    fn default() -> Self {
+        Self::new(0, Duration::from_secs(10), None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiskGuard, Quota, QuotaPolicy};
+    use crate::downloader::stats::Stats;
+    use crate::error::Error;
+    use std::time::Duration;
+
+    #[test]
+    fn quota() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("stream-gears-guard-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let segment = |i: u32| -> std::io::Result<_> {
+            let path = dir.join(format!("{i}.flv"));
+            std::fs::write(&path, [0; 100])?;
+            Ok(path)
+        };
+        let stats = Stats::new();
+        stats.new_file(dir.join("current").to_str().unwrap(), 50);
+        let quota = |policy| Quota {
+            max_bytes: 260,
+            policy,
+        };
+
+        let mut guard = DiskGuard::new(0, Duration::ZERO, Some(quota(QuotaPolicy::DeleteOldest)));
+        guard.rotated(segment(1)?, &stats)?;
+        guard.rotated(segment(2)?, &stats)?;
+        guard.rotated(segment(3)?, &stats)?;
+        assert!(!dir.join("1.flv").exists());
+        assert!(dir.join("2.flv").exists());
+
+        let mut guard = DiskGuard::new(0, Duration::ZERO, Some(quota(QuotaPolicy::Stop)));
+        guard.rotated(segment(4)?, &stats)?;
+        guard.rotated(segment(5)?, &stats)?;
+        assert!(matches!(
+            guard.rotated(segment(6)?, &stats),
+            Err(Error::QuotaExceeded(260))
+        ));
+
+        let mut guard = DiskGuard::new(u64::MAX, Duration::ZERO, None);
+        assert!(matches!(
+            guard.check(&stats),
+            Err(Error::LowDiskSpace { .. })
+        ));
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn stop() {
+        let stats = Stats::new();
+        let mut guard = DiskGuard::new(0, Duration::from_secs(3600), None);
+        assert!(guard.check(&stats).is_ok());
+        stats.clone().stop();
+        assert!(matches!(guard.check(&stats), Err(Error::Stopped)));
+    }
+}
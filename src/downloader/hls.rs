@@ -1,10 +1,13 @@
-use crate::downloader::util::format_filename;
-use crate::error::Result;
+use crate::downloader::guard::DiskGuard;
+use crate::downloader::stats::Stats;
+use crate::downloader::util::{create_unique, render_filename};
+use crate::error::{Error, Result};
 use crate::Segment;
 use m3u8_rs::Playlist;
 use reqwest::header::HeaderMap;
 use std::fs::File;
 use std::io::{BufWriter, Write};
+use std::path::PathBuf;
 use std::time::Duration;
 use tracing::{debug, error, info, warn};
 use url::Url;
@@ -14,20 +17,22 @@ pub fn download(
     headers: &HeaderMap,
     file_name: &str,
     mut splitting: Segment,
+    stats: &Stats,
+    mut guard: DiskGuard,
 ) -> Result<()> {
-    println!("Downloading {}...", url);
+    info!("Downloading HLS stream");
     let resp = super::get_response(url, headers)?;
-    println!("{}", resp.status());
+    debug!(status = %resp.status(), "Received playlist");
     // let mut resp = resp.bytes_stream();
     let bytes = resp.bytes()?;
-    let mut ts_file = TsFile::new(file_name);
+    let mut ts_file = create_ts_file(file_name, stats)?;
 
     let mut media_url = Url::parse(url)?;
     let mut pl = match m3u8_rs::parse_playlist(&bytes) {
         Ok((_i, Playlist::MasterPlaylist(pl))) => {
-            println!("Master playlist:\n{:#?}", pl);
+            debug!(variants = pl.variants.len(), "Master playlist");
             media_url = media_url.join(&pl.variants[0].uri)?;
-            println!("media url: {media_url}");
+            info!(%media_url, "Following the first variant");
             let resp = super::get_response(media_url.as_str(), headers)?;
             let bs = resp.bytes()?;
             // println!("{:?}", bs);
@@ -40,8 +45,12 @@ pub fn download(
             }
         }
         Ok((_i, Playlist::MediaPlaylist(pl))) => {
-            println!("Media playlist:\n{:#?}", pl);
-            println!("index {}", pl.media_sequence);
+            debug!(
+                media_sequence = pl.media_sequence,
+                segments = pl.segments.len(),
+                target_duration = pl.target_duration,
+                "Media playlist"
+            );
             pl
         }
         Err(e) => panic!("Parsing error: \n{}", e),
@@ -49,11 +58,10 @@ pub fn download(
     let mut previous_last_segment = 0;
     loop {
         if pl.segments.is_empty() {
-            println!("Segments array is empty - stream finished");
+            info!("Playlist has no segments, stream finished");
             break;
         }
-        let mut seq = pl.media_sequence;
-        for segment in &pl.segments {
+        for (seq, segment) in (pl.media_sequence..).zip(&pl.segments) {
             if seq > previous_last_segment {
                 if (previous_last_segment > 0) && (seq > (previous_last_segment + 1)) {
                     warn!("SEGMENT INFO SKIPPED");
@@ -61,7 +69,9 @@ pub fn download(
                 debug!("Yield segment");
                 if segment.discontinuity {
                     warn!("#EXT-X-DISCONTINUITY");
-                    ts_file = TsFile::new(file_name);
+                    let finished = PathBuf::from(format!("{}.ts", ts_file.name));
+                    ts_file = create_ts_file(file_name, stats)?;
+                    guard.rotated(finished, stats)?;
                     splitting = Segment::from_seg(splitting);
                 }
                 let length = download_to_file(
@@ -69,13 +79,20 @@ pub fn download(
                     headers,
                     &mut ts_file.buf_writer,
                 )?;
+                stats.write_bytes(length, Duration::from_secs_f32(segment.duration.max(0.)));
+                if stats.take_title_change() {
+                    splitting.meta_changed();
+                }
                 if splitting.needed_delta(length, Duration::from_secs(segment.duration as u64)) {
-                    ts_file = TsFile::new(file_name);
+                    let finished = PathBuf::from(format!("{}.ts", ts_file.name));
+                    ts_file = create_ts_file(file_name, stats)?;
                     info!("{} splitting.{splitting:?}", ts_file.name);
+                    guard.rotated(finished, stats)?;
+                } else {
+                    guard.check(stats)?;
                 }
                 previous_last_segment = seq;
             }
-            seq += 1;
         }
         let resp = super::get_response(media_url.as_str(), headers)?;
         let bs = resp.bytes()?;
@@ -83,10 +100,17 @@ pub fn download(
             pl = playlist;
         }
     }
-    println!("Done...");
+    info!("HLS download finished");
     Ok(())
 }
 
+fn create_ts_file(template: &str, stats: &Stats) -> Result<TsFile> {
+    let name = render_filename(template, &stats.snapshot());
+    let ts_file = TsFile::create(&name).map_err(Error::file(format!("{name}.ts.part")))?;
+    stats.new_file(&ts_file.name, 0);
+    Ok(ts_file)
+}
+
 fn download_to_file(url: Url, headers: &HeaderMap, out: &mut impl Write) -> reqwest::Result<u64> {
     debug!("url: {url}");
     let mut response = super::get_response(url.as_str(), headers)?;
@@ -103,19 +127,22 @@ pub struct TsFile {
 }
 
 impl TsFile {
-    pub fn new(file_name: &str) -> Self {
-        let file_name = format_filename(file_name);
-        let out = File::create(format!("{file_name}.ts.part")).expect("Unable to create ts file.");
+    /// Creates `{file_name}.ts.part` without any further formatting of the name,
+    /// appending a suffix if the file already exists.
+    pub fn create(file_name: &str) -> std::io::Result<Self> {
+        let (file_name, out) = create_unique(file_name, "ts")?;
         let buf_writer = BufWriter::new(out);
-        Self {
+        Ok(Self {
             buf_writer,
             name: file_name,
-        }
+        })
     }
 }
 
 impl Drop for TsFile {
     fn drop(&mut self) {
+        // Readers tailing the file take the rename as the end of the recording.
+        self.buf_writer.flush().unwrap_or_else(|e| error!("{e}"));
         std::fs::rename(
             format!("{}.ts.part", self.name),
             format!("{}.ts", self.name),
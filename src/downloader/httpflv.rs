@@ -1,4 +1,6 @@
-use crate::downloader::util::Segment;
+use crate::downloader::guard::DiskGuard;
+use crate::downloader::stats::Stats;
+use crate::downloader::util::{render_filename, Segment};
 use crate::flv_parser::{
     aac_audio_packet_header, avc_video_packet_header, script_data, tag_data, tag_header,
     AACPacketType, AVCPacketType, CodecId, FrameType, SoundFormat, TagData, TagHeader,
@@ -7,24 +9,37 @@ use crate::flv_writer::{FlvFile, FlvTag, TagDataHeader};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use nom::{Err, IResult};
 use std::io::{ErrorKind, Read};
+use std::path::PathBuf;
 use std::time::Duration;
 use tracing::{info, warn};
 
-pub fn download<T: Read>(connection: Connection<T>, file_name: &str, segment: Segment) {
-    match parse_flv(connection, file_name, segment) {
+/// Records the FLV stream of `connection`. Stopping it through `stats` is not an error, the
+/// [`DiskGuard`] stopping it is.
+pub fn download<T: Read>(
+    connection: Connection<T>,
+    file_name: &str,
+    segment: Segment,
+    stats: &Stats,
+    mut guard: DiskGuard,
+) -> core::result::Result<(), crate::error::Error> {
+    match parse_flv(connection, file_name, segment, stats, &mut guard) {
         Ok(_) => {
             info!("Done... {file_name}");
         }
-        Err(e) => {
-            warn!("{e}")
+        Err(crate::error::Error::Stopped) => {
+            info!("Stopped... {file_name}");
         }
+        Err(e) => return Err(e),
     }
+    Ok(())
 }
 
 fn parse_flv<T: Read>(
     mut connection: Connection<T>,
     file_name: &str,
     mut segment: Segment,
+    stats: &Stats,
+    guard: &mut DiskGuard,
 ) -> core::result::Result<(), crate::error::Error> {
     let mut flv_tags_cache: Vec<(TagHeader, Bytes, Bytes)> = Vec::new();
 
@@ -35,7 +50,8 @@ fn parse_flv<T: Read>(
     // let mut writer = BufWriter::new(file);
     // flv_writer::to_json(&mut writer, &header)?;
 
-    let mut out = FlvFile::new(file_name)?;
+    // Created on the first keyframe, so the sequence headers are known to the file name template.
+    let mut out: Option<FlvFile> = None;
     let mut downloaded_size = 9 + 4;
     let mut on_meta_data = None;
     let mut aac_sequence_header = None;
@@ -71,6 +87,7 @@ fn parse_flv<T: Read>(
                             // panic!("Unexpected aac_sequence_header tag.");
                             // create_new = true;
                         }
+                        stats.aac_sequence_header(&bytes);
                         aac_sequence_header =
                             Some((tag_header, bytes.clone(), previous_tag_size.clone()))
                     }
@@ -95,6 +112,7 @@ fn parse_flv<T: Read>(
                     let (_, avc_video_header) = avc_video_packet_header(video_data.video_data)
                         .expect("Error in parsing avc video packet header.");
                     if avc_video_header.packet_type == AVCPacketType::SequenceHeader {
+                        stats.avc_sequence_header(&bytes);
                         h264_sequence_header = match h264_sequence_header {
                             None => Some((tag_header, bytes.clone(), previous_tag_size.clone())),
                             Some((_, binary_data, _)) => {
@@ -128,8 +146,11 @@ fn parse_flv<T: Read>(
             }
             TagData::Script => {
                 let (_, tag_data) = script_data(i).expect("Error in parsing script tag.");
-                if on_meta_data.is_some() {
+                if let Some((_, previous_bytes, _)) = &on_meta_data {
                     warn!("Unexpected script tag. {tag_header:?}");
+                    if previous_bytes != &bytes {
+                        segment.meta_changed();
+                    }
                     // create_new = true;
 
                     // panic!("Unexpected script tag.");
@@ -152,16 +173,24 @@ fn parse_flv<T: Read>(
                     },
                 ..
             } => {
-                if segment.needed(
+                if stats.take_title_change() {
+                    segment.meta_changed();
+                }
+                let previous = out.as_ref().map(|out| out.name.clone());
+                let needed = segment.needed(
                     downloaded_size,
                     Duration::from_millis(flv_tag.header.timestamp as u64),
-                ) {
+                );
+                if out.is_none() {
+                    out = Some(create_flv_file(file_name, stats)?);
+                } else if needed {
                     // let new_file_name = format_filename(file_name);
                     downloaded_size = 9 + 4;
-                    out = FlvFile::new(file_name)?;
+                    let out = out.insert(create_flv_file(file_name, stats)?);
                     let on_meta_data = on_meta_data.as_ref().expect("on_meta_data does not exist");
                     // onMetaData
                     out.write_tag(&on_meta_data.0, &on_meta_data.1, &on_meta_data.2)?;
+                    stats.write_tag(&on_meta_data.0);
                     // AACSequenceHeader
                     let aac_sequence_header = aac_sequence_header
                         .as_ref()
@@ -171,6 +200,7 @@ fn parse_flv<T: Read>(
                         &aac_sequence_header.1,
                         &aac_sequence_header.2,
                     )?;
+                    stats.write_tag(&aac_sequence_header.0);
                     // H264SequenceHeader
                     let h264_sequence_header = h264_sequence_header
                         .as_ref()
@@ -180,14 +210,17 @@ fn parse_flv<T: Read>(
                         &h264_sequence_header.1,
                         &h264_sequence_header.2,
                     )?;
+                    stats.write_tag(&h264_sequence_header.0);
                     info!("{} splitting.{segment:?}", out.name);
                 }
+                let out = out.as_mut().expect("flv file does not exist");
 
                 for (tag_header, flv_tag_data, previous_tag_size_bytes) in &flv_tags_cache {
                     if tag_header.timestamp < prev_timestamp {
                         warn!("Non-monotonous DTS in output stream; previous: {prev_timestamp}, current: {};", tag_header.timestamp);
                     }
                     out.write_tag(tag_header, flv_tag_data, previous_tag_size_bytes)?;
+                    stats.write_tag(tag_header);
                     // out.write_tag_header( tag_header)?;
                     // out.write(flv_tag_data)?;
                     // out.write(previous_tag_size_bytes)?;
@@ -198,12 +231,13 @@ fn parse_flv<T: Read>(
                 flv_tags_cache.clear();
                 if create_new {
                     // let new_file_name = format_filename(file_name);
-                    out = FlvFile::new(file_name)?;
+                    *out = create_flv_file(file_name, stats)?;
                     // let on_meta_data = on_meta_data.as_ref().unwrap();
                     // flv_tags_cache.push(on_meta_data)
                     // onMetaData
                     let on_meta_data = on_meta_data.as_ref().expect("on_meta_data does not exist");
                     out.write_tag(&on_meta_data.0, &on_meta_data.1, &on_meta_data.2)?;
+                    stats.write_tag(&on_meta_data.0);
                     // AACSequenceHeader
                     let aac_sequence_header = aac_sequence_header
                         .as_ref()
@@ -213,9 +247,16 @@ fn parse_flv<T: Read>(
                         &aac_sequence_header.1,
                         &aac_sequence_header.2,
                     )?;
+                    stats.write_tag(&aac_sequence_header.0);
                     create_new = false;
                     info!("{} splitting.", out.name);
                 }
+                match previous {
+                    Some(name) if name != out.name => {
+                        guard.rotated(PathBuf::from(format!("{name}.flv")), stats)?
+                    }
+                    _ => guard.check(stats)?,
+                }
                 flv_tags_cache.push((tag_header, bytes.clone(), previous_tag_size.clone()));
             }
             _ => {
@@ -227,6 +268,12 @@ fn parse_flv<T: Read>(
     Ok(())
 }
 
+fn create_flv_file(template: &str, stats: &Stats) -> std::io::Result<FlvFile> {
+    let out = FlvFile::create(&render_filename(template, &stats.snapshot()))?;
+    stats.new_file(&out.name, 9 + 4);
+    Ok(out)
+}
+
 // fn is_splitting(
 //     flv_tag: FlvTag,
 //     segment: &Segment,
@@ -291,7 +338,7 @@ impl<T: Read> Connection<T> {
         loop {
             if chunk_size <= self.buffer.len() {
                 let bytes = Bytes::copy_from_slice(&self.buffer[..chunk_size]);
-                self.buffer.advance(chunk_size as usize);
+                self.buffer.advance(chunk_size);
                 return Ok(bytes);
             }
             // BytesMut::with_capacity(0).deref_mut()
@@ -354,4 +401,31 @@ mod tests {
         //     "test.flv")?;
         Ok(())
     }
+
+    #[test]
+    fn returns_errors() {
+        use super::{download, Connection};
+        use crate::downloader::guard::DiskGuard;
+        use crate::downloader::stats::Stats;
+        use crate::downloader::util::Segment;
+        use std::io::Cursor;
+        use std::time::Duration;
+
+        let record = |data: Vec<u8>| {
+            download(
+                Connection::new(Cursor::new(data)),
+                "returns-errors",
+                Segment::Any(Vec::new()),
+                &Stats::new(),
+                DiskGuard::new(0, Duration::from_secs(10), None),
+            )
+        };
+        // A stream that ends after the first previous tag size is done.
+        assert!(record(vec![0; 4]).is_ok());
+        // One that ends within a tag header is not.
+        assert!(matches!(
+            record(vec![0, 0, 0, 0, 9, 0, 0, 0, 0]),
+            Err(crate::error::Error::NomIncomplete(..))
+        ));
+    }
 }
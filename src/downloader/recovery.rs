@@ -0,0 +1,248 @@
+use crate::error::Result;
+use std::fs::OpenOptions;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+const TS_PACKET_SIZE: u64 = 188;
+
+#[derive(Debug)]
+pub struct Recovered {
+    /// Final path after the `.part` suffix was removed.
+    pub path: PathBuf,
+    /// Bytes cut off the end of the file.
+    pub truncated: u64,
+}
+
+/// Finalizes the `.flv.part` and `.ts.part` files left in `dir` by a killed recording.
+///
+/// Each file is truncated to its last complete FLV tag or TS packet and renamed to its final
+/// name. With `rewrite_metadata`, `duration` and `filesize` in the FLV onMetaData are updated.
+/// Files that are not recoverable, e.g. without a valid FLV header, are left untouched, and so
+/// are those a running recording still holds the lock of.
+pub fn recover_dir(dir: &Path, rewrite_metadata: bool) -> Result<Vec<Recovered>> {
+    let mut recovered = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(crate::error::Error::file(dir))? {
+        let path = entry?.path();
+        let file_name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(file_name) => file_name,
+            None => continue,
+        };
+        if !file_name.ends_with(".flv.part") && !file_name.ends_with(".ts.part") {
+            continue;
+        }
+        let file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Unable to recover {}: {e}", path.display());
+                continue;
+            }
+        };
+        if fs2::FileExt::try_lock_exclusive(&file).is_err() {
+            info!("Skipping {}, it is still being recorded", path.display());
+            continue;
+        }
+        let result = if file_name.ends_with(".flv.part") {
+            recover_flv(&path, rewrite_metadata)
+        } else if file_name.ends_with(".ts.part") {
+            recover_ts(&path)
+        } else {
+            continue;
+        };
+        match result {
+            Ok(Some(r)) => {
+                info!(
+                    "Recovered {} ({} bytes truncated)",
+                    r.path.display(),
+                    r.truncated
+                );
+                recovered.push(r)
+            }
+            Ok(None) => warn!("Unable to recover {}", path.display()),
+            Err(e) => warn!("Unable to recover {}: {e}", path.display()),
+        }
+    }
+    Ok(recovered)
+}
+
+pub fn recover_flv(path: &Path, rewrite_metadata: bool) -> Result<Option<Recovered>> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(&mut file);
+    let mut header = [0u8; 13];
+    if reader.read_exact(&mut header).is_err() || &header[..3] != b"FLV" {
+        return Ok(None);
+    }
+    let mut valid = 13;
+    let mut metadata = None;
+    let mut first_timestamp = None;
+    let mut last_timestamp = 0;
+    let mut tag_header = [0u8; 11];
+    while valid + 11 <= len {
+        reader.read_exact(&mut tag_header)?;
+        let tag_type = tag_header[0];
+        let data_size = u32::from_be_bytes([0, tag_header[1], tag_header[2], tag_header[3]]);
+        let timestamp =
+            u32::from_be_bytes([tag_header[7], tag_header[4], tag_header[5], tag_header[6]]);
+        let end = valid + 11 + data_size as u64 + 4;
+        if !matches!(tag_type, 8 | 9 | 18) || end > len {
+            break;
+        }
+        let mut body = vec![0u8; data_size as usize + 4];
+        reader.read_exact(&mut body)?;
+        let previous_tag_size = u32::from_be_bytes(body[data_size as usize..].try_into().unwrap());
+        if previous_tag_size != 11 + data_size {
+            break;
+        }
+        if tag_type == 18 {
+            if metadata.is_none() {
+                metadata = Some((valid + 11, body));
+            }
+        } else {
+            first_timestamp.get_or_insert(timestamp);
+            last_timestamp = timestamp;
+        }
+        valid = end;
+    }
+    drop(reader);
+    file.set_len(valid)?;
+    if let (true, Some((offset, mut body))) = (rewrite_metadata, metadata) {
+        let duration = last_timestamp.saturating_sub(first_timestamp.unwrap_or(0));
+        let mut changed = set_number(&mut body, b"duration", duration as f64 / 1000.);
+        changed |= set_number(&mut body, b"filesize", valid as f64);
+        if changed {
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(&body)?;
+        }
+    }
+    drop(file);
+    let path = finalize(path)?;
+    Ok(Some(Recovered {
+        path,
+        truncated: len - valid,
+    }))
+}
+
+pub fn recover_ts(path: &Path) -> Result<Option<Recovered>> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(&mut file);
+    let mut packet = [0u8; TS_PACKET_SIZE as usize];
+    let mut valid = 0;
+    while valid + TS_PACKET_SIZE <= len {
+        reader.read_exact(&mut packet)?;
+        if packet[0] != 0x47 {
+            break;
+        }
+        valid += TS_PACKET_SIZE;
+    }
+    drop(reader);
+    if valid == 0 {
+        return Ok(None);
+    }
+    file.set_len(valid)?;
+    drop(file);
+    let path = finalize(path)?;
+    Ok(Some(Recovered {
+        path,
+        truncated: len - valid,
+    }))
+}
+
+/// Overwrites the AMF0 number stored under `key` in place.
+fn set_number(body: &mut [u8], key: &[u8], value: f64) -> bool {
+    let mut pattern = (key.len() as u16).to_be_bytes().to_vec();
+    pattern.extend_from_slice(key);
+    // AMF0 number marker
+    pattern.push(0);
+    match body
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+    {
+        Some(start) if start + pattern.len() + 8 <= body.len() => {
+            let start = start + pattern.len();
+            body[start..start + 8].copy_from_slice(&value.to_be_bytes());
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Renames `name.ext.part` to `name.ext`, or to `name_N.ext` if that already exists.
+fn finalize(part: &Path) -> std::io::Result<PathBuf> {
+    let path = part.with_extension("");
+    let stem = path.with_extension("");
+    let extension = path.extension().unwrap_or_default().to_owned();
+    let mut target = path.clone();
+    let mut suffix = 1;
+    while target.exists() {
+        let mut name = stem.file_name().unwrap_or_default().to_owned();
+        name.push(format!("_{suffix}."));
+        name.push(&extension);
+        target = stem.with_file_name(name);
+        suffix += 1;
+    }
+    std::fs::rename(part, &target)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::recover_dir;
+    use crate::downloader::util::create_unique;
+
+    fn tag(tag_type: u8, timestamp: u32, body: &[u8]) -> Vec<u8> {
+        let mut tag = vec![tag_type];
+        tag.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        tag.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+        tag.extend_from_slice(&[(timestamp >> 24) as u8, 0, 0, 0]);
+        tag.extend_from_slice(body);
+        tag.extend_from_slice(&(11 + body.len() as u32).to_be_bytes());
+        tag
+    }
+
+    #[test]
+    fn truncate_and_rename() -> anyhow::Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("stream-gears-recovery-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let mut meta = vec![2, 0, 10];
+        meta.extend_from_slice(b"onMetaData");
+        meta.extend_from_slice(&[8, 0, 0, 0, 1, 0, 8]);
+        meta.extend_from_slice(b"duration");
+        meta.push(0);
+        meta.extend_from_slice(&0f64.to_be_bytes());
+        meta.extend_from_slice(&[0, 0, 9]);
+        let mut flv = b"FLV\x01\x05\0\0\0\x09\0\0\0\0".to_vec();
+        flv.extend(tag(18, 0, &meta));
+        flv.extend(tag(9, 1000, &[0x17, 1, 0, 0, 0]));
+        flv.extend(tag(9, 3500, &[0x27, 1, 0, 0, 0]));
+        let complete = flv.len();
+        // Half-written tag of a killed process.
+        flv.extend(&tag(8, 3520, &[0xaf; 100])[..50]);
+        std::fs::write(dir.join("rec.flv.part"), &flv)?;
+        std::fs::write(dir.join("rec.ts.part"), [0x47; 188 * 2 + 100])?;
+        std::fs::write(dir.join("rec.ts"), [0x47; 188])?;
+        // Still being written by a recording in this directory.
+        let (_, _live) = create_unique(dir.join("live").to_str().unwrap(), "flv")?;
+
+        let mut recovered = recover_dir(&dir, true)?;
+        recovered.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(recovered.len(), 2);
+        assert_eq!(recovered[0].path, dir.join("rec.flv"));
+        assert_eq!(recovered[0].truncated, 50);
+        assert_eq!(recovered[1].path, dir.join("rec_1.ts"));
+        assert_eq!(recovered[1].truncated, 100);
+        assert_eq!(std::fs::metadata(dir.join("rec_1.ts"))?.len(), 188 * 2);
+        let flv = std::fs::read(dir.join("rec.flv"))?;
+        assert_eq!(flv.len(), complete);
+        let duration = flv
+            .windows(8)
+            .position(|w| w == b"duration")
+            .map(|i| f64::from_be_bytes(flv[i + 9..i + 17].try_into().unwrap()));
+        assert_eq!(duration, Some(2.5));
+        assert!(dir.join("live.flv.part").exists());
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
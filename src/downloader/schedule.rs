@@ -0,0 +1,193 @@
+use crate::error::{Error, Result};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone, Timelike};
+use std::str::FromStr;
+
+/// A wall-clock schedule in the five field cron syntax
+/// (`minute hour day-of-month month day-of-week`), evaluated in local time.
+///
+/// Each field accepts `*`, single values, ranges (`1-5`), steps (`*/15`, `0-30/10`) and
+/// comma separated lists of those. Day of week counts from Sunday = 0, 7 is also Sunday.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl Schedule {
+    /// Fires at the start of every hour.
+    pub fn hourly() -> Self {
+        "0 * * * *".parse().expect("valid hourly schedule")
+    }
+
+    /// Fires every day at `hour:minute` local time.
+    pub fn daily(hour: u32, minute: u32) -> Result<Self> {
+        format!("{minute} {hour} * * *").parse()
+    }
+
+    /// Returns the first point in time strictly after `after` matching the schedule.
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = after.naive_local() + Duration::minutes(1);
+        let mut date = start.date();
+        // Any valid schedule fires at least once in 8 years (Feb 29 on a given weekday).
+        for _ in 0..366 * 8 {
+            if self.matches_date(date) {
+                let first_hour = if date == start.date() {
+                    start.hour()
+                } else {
+                    0
+                };
+                for hour in first_hour..24 {
+                    if !contains(self.hours, hour) {
+                        continue;
+                    }
+                    let first_minute = if date == start.date() && hour == start.hour() {
+                        start.minute()
+                    } else {
+                        0
+                    };
+                    for minute in first_minute..60 {
+                        if !contains(self.minutes, minute) {
+                            continue;
+                        }
+                        // Skip times that do not exist because of a DST transition.
+                        if let Some(time) = Local
+                            .from_local_datetime(&date.and_hms(hour, minute, 0))
+                            .earliest()
+                        {
+                            return Some(time);
+                        }
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if !contains(self.months, date.month()) {
+            return false;
+        }
+        let day_of_month = contains(self.days_of_month, date.day());
+        let day_of_week = contains(self.days_of_week, date.weekday().num_days_from_sunday());
+        // Like cron, restricting both day fields matches either of them.
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, true) => true,
+            (true, false) => day_of_week,
+            (false, true) => day_of_month,
+            (false, false) => day_of_month || day_of_week,
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(Error::InvalidSchedule(
+                s.to_string(),
+                "expected 5 fields".to_string(),
+            ));
+        }
+        let field = |i: usize, min: u32, max: u32| {
+            parse_field(fields[i], min, max)
+                .ok_or_else(|| Error::InvalidSchedule(s.to_string(), fields[i].to_string()))
+        };
+        let mut days_of_week = field(4, 0, 7)?;
+        if contains(days_of_week, 7) {
+            days_of_week |= 1;
+        }
+        Ok(Self {
+            minutes: field(0, 0, 59)?,
+            hours: field(1, 0, 23)?,
+            days_of_month: field(2, 1, 31)?,
+            months: field(3, 1, 12)?,
+            days_of_week,
+            any_day_of_month: fields[2] == "*",
+            any_day_of_week: fields[4] == "*",
+        })
+    }
+}
+
+fn contains(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|&step| step > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+                None => {
+                    let value = range.parse().ok()?;
+                    // `5/15` means every 15 starting at 5.
+                    (value, if step > 1 { max } else { value })
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step) {
+            set |= 1 << value;
+        }
+    }
+    Some(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Schedule;
+    use chrono::{Local, TimeZone};
+
+    #[test]
+    fn next_after() {
+        let now = Local.ymd(2022, 5, 20).and_hms(13, 27, 40);
+
+        let hourly = Schedule::hourly();
+        assert_eq!(
+            hourly.next_after(now),
+            Some(Local.ymd(2022, 5, 20).and_hms(14, 0, 0))
+        );
+
+        let midnight = Schedule::daily(0, 0).unwrap();
+        assert_eq!(
+            midnight.next_after(now),
+            Some(Local.ymd(2022, 5, 21).and_hms(0, 0, 0))
+        );
+
+        let quarters: Schedule = "*/15 * * * *".parse().unwrap();
+        assert_eq!(
+            quarters.next_after(now),
+            Some(Local.ymd(2022, 5, 20).and_hms(13, 30, 0))
+        );
+
+        // 2022-05-20 is a Friday.
+        let weekend: Schedule = "30 20 * * 6,0".parse().unwrap();
+        assert_eq!(
+            weekend.next_after(now),
+            Some(Local.ymd(2022, 5, 21).and_hms(20, 30, 0))
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!("* * * *".parse::<Schedule>().is_err());
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("*/0 * * * *".parse::<Schedule>().is_err());
+        assert!("5-1 * * * *".parse::<Schedule>().is_err());
+    }
+}
@@ -0,0 +1,303 @@
+use crate::flv_parser::{
+    aac_audio_packet_header, audio_specific_config, avc_decoder_configuration_record,
+    avc_video_packet_header, sps_resolution, TagHeader, TagType,
+};
+use pyo3::pyclass;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Shared handle to the live statistics of a recording.
+///
+/// Cloning is cheap and every clone observes the same counters, so one copy can be handed to
+/// `parse_flv`/`hls::download` while another one is polled from Rust or Python.
+#[derive(Clone)]
+pub struct Stats {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    started: Instant,
+    /// Timestamps of the last audio and video tag, which are interleaved in the stream.
+    last_audio: Option<u32>,
+    last_video: Option<u32>,
+    /// Highest timestamp so far, the recording lasts up to it.
+    max_timestamp: Option<u32>,
+    title_changed: bool,
+    stopped: bool,
+    snapshot: Snapshot,
+}
+
+/// A point-in-time copy of [`Stats`].
+#[pyclass]
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Snapshot {
+    /// Bytes written to disk over all segments.
+    #[pyo3(get)]
+    pub bytes_written: u64,
+    /// Bytes written to the current segment.
+    #[pyo3(get)]
+    pub file_bytes: u64,
+    /// Media duration written so far, in milliseconds.
+    #[pyo3(get)]
+    pub duration_ms: u64,
+    #[pyo3(get)]
+    pub audio_tags: u64,
+    #[pyo3(get)]
+    pub video_tags: u64,
+    #[pyo3(get)]
+    pub script_tags: u64,
+    /// Audio or video tags whose timestamp went backwards compared to the previous tag of the
+    /// same stream.
+    #[pyo3(get)]
+    pub non_monotonic_timestamps: u64,
+    /// Number of files created so far.
+    #[pyo3(get)]
+    pub files: u64,
+    /// Name of the file currently being written, without the extension.
+    #[pyo3(get)]
+    pub file_name: Option<String>,
+    /// Room the recording belongs to, used by the `{room}` file name placeholder.
+    #[pyo3(get)]
+    pub room: Option<String>,
+    /// Stream title as last reported through [`Stats::set_title`].
+    #[pyo3(get)]
+    pub title: Option<String>,
+    #[pyo3(get)]
+    pub video_codec: Option<String>,
+    #[pyo3(get)]
+    pub video_profile: Option<u8>,
+    #[pyo3(get)]
+    pub video_level: Option<u8>,
+    #[pyo3(get)]
+    pub width: Option<u32>,
+    #[pyo3(get)]
+    pub height: Option<u32>,
+    #[pyo3(get)]
+    pub audio_codec: Option<String>,
+    #[pyo3(get)]
+    pub sample_rate: Option<u32>,
+    #[pyo3(get)]
+    pub channels: Option<u8>,
+    /// Wall-clock seconds since the recording started.
+    #[pyo3(get)]
+    pub elapsed_secs: f64,
+    /// Average bytes per second written since the recording started.
+    #[pyo3(get)]
+    pub throughput: f64,
+    /// Average media bitrate in kbit/s.
+    #[pyo3(get)]
+    pub bitrate_kbps: f64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                started: Instant::now(),
+                last_audio: None,
+                last_video: None,
+                max_timestamp: None,
+                title_changed: false,
+                stopped: false,
+                snapshot: Default::default(),
+            })),
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let inner = self.inner.lock().unwrap();
+        let mut snapshot = inner.snapshot.clone();
+        let elapsed = inner.started.elapsed().as_secs_f64();
+        snapshot.elapsed_secs = elapsed;
+        if elapsed > 0. {
+            snapshot.throughput = snapshot.bytes_written as f64 / elapsed;
+        }
+        if snapshot.duration_ms > 0 {
+            snapshot.bitrate_kbps =
+                snapshot.bytes_written as f64 * 8. / snapshot.duration_ms as f64;
+        }
+        snapshot
+    }
+
+    /// Asks the recording to finish its current file and return, see [`DiskGuard::check`].
+    ///
+    /// [`DiskGuard::check`]: crate::downloader::guard::DiskGuard::check
+    pub fn stop(&self) {
+        self.inner.lock().unwrap().stopped = true;
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.inner.lock().unwrap().stopped
+    }
+
+    pub fn new_file(&self, file_name: &str, header_size: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.snapshot.files += 1;
+        inner.snapshot.file_name = Some(file_name.to_string());
+        inner.snapshot.file_bytes = header_size;
+        inner.snapshot.bytes_written += header_size;
+    }
+
+    pub fn set_room(&self, room: &str) {
+        self.inner.lock().unwrap().snapshot.room = Some(room.to_string());
+    }
+
+    /// Updates the stream title. A change from a previously known title is reported once by
+    /// [`Stats::take_title_change`].
+    pub fn set_title(&self, title: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        match &inner.snapshot.title {
+            Some(old) if old == title => return,
+            Some(_) => inner.title_changed = true,
+            None => {}
+        }
+        inner.snapshot.title = Some(title.to_string());
+    }
+
+    pub fn take_title_change(&self) -> bool {
+        std::mem::take(&mut self.inner.lock().unwrap().title_changed)
+    }
+
+    /// Records an FLV tag that has been written to the current file.
+    pub fn write_tag(&self, tag_header: &TagHeader) {
+        let mut inner = self.inner.lock().unwrap();
+        let size = (11 + tag_header.data_size + 4) as u64;
+        inner.snapshot.bytes_written += size;
+        inner.snapshot.file_bytes += size;
+        match tag_header.tag_type {
+            TagType::Audio => inner.snapshot.audio_tags += 1,
+            TagType::Video => inner.snapshot.video_tags += 1,
+            TagType::Script => inner.snapshot.script_tags += 1,
+        }
+        let inner = &mut *inner;
+        let timestamp = tag_header.timestamp;
+        let last = match tag_header.tag_type {
+            TagType::Audio => &mut inner.last_audio,
+            TagType::Video => &mut inner.last_video,
+            TagType::Script => return,
+        };
+        if last.is_some_and(|last| timestamp < last) {
+            inner.snapshot.non_monotonic_timestamps += 1;
+        }
+        *last = Some(timestamp);
+        match inner.max_timestamp {
+            Some(max) if timestamp > max => inner.snapshot.duration_ms += (timestamp - max) as u64,
+            Some(_) => return,
+            None => {}
+        }
+        inner.max_timestamp = Some(timestamp);
+    }
+
+    /// Records an opaque chunk of media, e.g. an HLS segment.
+    pub fn write_bytes(&self, size: u64, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.snapshot.bytes_written += size;
+        inner.snapshot.file_bytes += size;
+        inner.snapshot.duration_ms += duration.as_millis() as u64;
+    }
+
+    /// Updates the video codec info from the body of an AVC sequence header tag.
+    pub fn avc_sequence_header(&self, body: &[u8]) {
+        let record = body
+            .get(1..)
+            .and_then(|i| avc_video_packet_header(i).ok())
+            .and_then(|(i, _)| avc_decoder_configuration_record(i).ok());
+        let (_, record) = match record {
+            Some(record) => record,
+            None => return warn!("Unable to parse avc decoder configuration record."),
+        };
+        let resolution = record
+            .sequence_parameter_sets
+            .first()
+            .and_then(|sps| sps_resolution(sps));
+        let mut inner = self.inner.lock().unwrap();
+        let snapshot = &mut inner.snapshot;
+        snapshot.video_codec = Some("H264".to_string());
+        snapshot.video_profile = Some(record.profile_indication);
+        snapshot.video_level = Some(record.level_indication);
+        snapshot.width = resolution.map(|(width, _)| width);
+        snapshot.height = resolution.map(|(_, height)| height);
+    }
+
+    /// Updates the audio codec info from the body of an AAC sequence header tag.
+    pub fn aac_sequence_header(&self, body: &[u8]) {
+        let config = body
+            .get(1..)
+            .and_then(|i| aac_audio_packet_header(i).ok())
+            .and_then(|(i, _)| audio_specific_config(i).ok());
+        let (_, config) = match config {
+            Some(config) => config,
+            None => return warn!("Unable to parse audio specific config."),
+        };
+        let mut inner = self.inner.lock().unwrap();
+        let snapshot = &mut inner.snapshot;
+        snapshot.audio_codec = Some("AAC".to_string());
+        snapshot.sample_rate = config.sampling_frequency();
+        snapshot.channels = Some(config.channel_configuration);
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stats;
+    use crate::flv_parser::{TagHeader, TagType};
+
+    #[test]
+    fn sequence_headers() {
+        let stats = Stats::new();
+        // 1920x1080 High profile, level 4.0 with 8 lines of bottom cropping.
+        stats.avc_sequence_header(&[
+            0x17, 0x00, 0x00, 0x00, 0x00, 0x01, 0x64, 0x00, 0x28, 0xff, 0xe1, 0x00, 0x0c, 0x67,
+            0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5, 0x40, 0x01, 0x00, 0x04,
+            0x68, 0xeb, 0xe3, 0xcb,
+        ]);
+        stats.aac_sequence_header(&[0xaf, 0x00, 0x12, 0x10]);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.video_codec.as_deref(), Some("H264"));
+        assert_eq!(snapshot.video_profile, Some(100));
+        assert_eq!(snapshot.width, Some(1920));
+        assert_eq!(snapshot.height, Some(1080));
+        assert_eq!(snapshot.sample_rate, Some(44100));
+        assert_eq!(snapshot.channels, Some(2));
+    }
+
+    #[test]
+    fn tag_counters() {
+        let stats = Stats::new();
+        stats.new_file("test", 13);
+        for (tag_type, timestamp) in [
+            (TagType::Script, 0),
+            (TagType::Video, 0),
+            (TagType::Audio, 20),
+            (TagType::Video, 40),
+            (TagType::Audio, 30),
+            (TagType::Video, 80),
+            (TagType::Video, 60),
+        ] {
+            stats.write_tag(&TagHeader {
+                tag_type,
+                data_size: 100,
+                timestamp,
+                stream_id: 0,
+            });
+        }
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.bytes_written, 13 + 7 * 115);
+        assert_eq!(snapshot.file_bytes, snapshot.bytes_written);
+        assert_eq!(snapshot.video_tags, 4);
+        assert_eq!(snapshot.audio_tags, 2);
+        assert_eq!(snapshot.script_tags, 1);
+        // Interleaved audio does not count, only the video going back from 80 to 60.
+        assert_eq!(snapshot.duration_ms, 80);
+        assert_eq!(snapshot.non_monotonic_timestamps, 1);
+        assert_eq!(snapshot.file_name.as_deref(), Some("test"));
+    }
+}
@@ -1,10 +1,20 @@
+use crate::downloader::schedule::Schedule;
+use crate::downloader::stats::Snapshot;
 use chrono::{DateTime, Local};
+use std::fs::File;
+use std::path::Path;
 use std::time::Duration;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Segment {
     Time(Duration, Duration),
     Size(u64, u64),
+    /// Splits whenever the wall-clock schedule fires, holds the next firing time.
+    Schedule(Schedule, Option<DateTime<Local>>),
+    /// Splits when onMetaData or the stream title changes, holds whether a change is pending.
+    MetaChange(bool),
+    /// Splits as soon as any of the inner rules is hit.
+    Any(Vec<Segment>),
 }
 
 impl Segment {
@@ -18,6 +28,27 @@ impl Segment {
                 *old = 0;
                 seg
             }
+            Segment::Schedule(_, _) => seg,
+            Segment::MetaChange(pending) => {
+                *pending = false;
+                seg
+            }
+            Segment::Any(segments) => {
+                *segments = std::mem::take(segments)
+                    .into_iter()
+                    .map(Segment::from_seg)
+                    .collect();
+                seg
+            }
+        }
+    }
+
+    /// Marks a metadata change, picked up by the next call to `needed` or `needed_delta`.
+    pub fn meta_changed(&mut self) {
+        match self {
+            Segment::MetaChange(pending) => *pending = true,
+            Segment::Any(segments) => segments.iter_mut().for_each(Segment::meta_changed),
+            _ => {}
         }
     }
 
@@ -30,6 +61,23 @@ impl Segment {
             Segment::Size(expected, _) if *expected <= actual_size => true,
             Segment::Time(_, _) => false,
             Segment::Size(_, _) => false,
+            Segment::Schedule(schedule, next) => schedule_needed(schedule, next),
+            Segment::MetaChange(pending) => std::mem::take(pending),
+            Segment::Any(segments) => {
+                // Every rule is evaluated so that their state keeps advancing.
+                let mut needed = false;
+                for seg in segments.iter_mut() {
+                    needed |= seg.needed(actual_size, actual_time);
+                }
+                if needed {
+                    for seg in segments {
+                        if let Segment::Time(_, start_time) = seg {
+                            *start_time = actual_time;
+                        }
+                    }
+                }
+                needed
+            }
         }
     }
 
@@ -51,6 +99,36 @@ impl Segment {
                 *previous += size;
                 false
             }
+            Segment::Schedule(schedule, next) => schedule_needed(schedule, next),
+            Segment::MetaChange(pending) => std::mem::take(pending),
+            Segment::Any(segments) => {
+                let mut needed = false;
+                for seg in segments.iter_mut() {
+                    needed |= seg.needed_delta(size, delta);
+                }
+                if needed {
+                    *segments = std::mem::take(segments)
+                        .into_iter()
+                        .map(Segment::from_seg)
+                        .collect();
+                }
+                needed
+            }
+        }
+    }
+}
+
+fn schedule_needed(schedule: &Schedule, next: &mut Option<DateTime<Local>>) -> bool {
+    let now = Local::now();
+    match next {
+        Some(at) if *at > now => false,
+        Some(_) => {
+            *next = schedule.next_after(now);
+            true
+        }
+        None => {
+            *next = schedule.next_after(now);
+            false
         }
     }
 }
@@ -62,3 +140,180 @@ pub fn format_filename(file_name: &str) -> String {
     // format!("{file_name}{time_str}")
     time_str.to_string()
 }
+
+/// Renders a file name template into a file name without extension.
+///
+/// `{index}`, `{room}`, `{title}`, `{codec}`, `{resolution}`, `{width}` and `{height}` are
+/// taken from the recording's [`Snapshot`], then the result goes through [`format_filename`]
+/// so that `%Y-%m-%d` style specifiers keep working. `/` in the template creates directories,
+/// while substituted values are sanitized so a title can never escape the template: path
+/// separators become `_`, as do values that are empty or made of dots only.
+pub fn render_filename(template: &str, snapshot: &Snapshot) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after
+            .find('}')
+            .and_then(|end| Some((end, placeholder(&after[..end], snapshot)?)));
+        match value {
+            Some((end, value)) => {
+                rendered.push_str(&sanitize(&value));
+                rest = &after[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    format_filename(&rendered)
+}
+
+fn placeholder(name: &str, snapshot: &Snapshot) -> Option<String> {
+    let unknown = || "unknown".to_string();
+    Some(match name {
+        "index" => (snapshot.files + 1).to_string(),
+        "room" => snapshot.room.clone().unwrap_or_else(unknown),
+        "title" => snapshot.title.clone().unwrap_or_else(unknown),
+        "codec" => snapshot.video_codec.clone().unwrap_or_else(unknown),
+        "resolution" => match (snapshot.width, snapshot.height) {
+            (Some(width), Some(height)) => format!("{width}x{height}"),
+            _ => unknown(),
+        },
+        "width" => snapshot.width.map_or_else(unknown, |w| w.to_string()),
+        "height" => snapshot.height.map_or_else(unknown, |h| h.to_string()),
+        _ => return None,
+    })
+}
+
+fn sanitize(value: &str) -> String {
+    let mut sanitized = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => sanitized.push('_'),
+            c if c.is_control() => sanitized.push('_'),
+            // Escaped for the strftime pass in `format_filename`.
+            '%' => sanitized.push_str("%%"),
+            c => sanitized.push(c),
+        }
+    }
+    // `.` and `..` would refer to the directory of the template or its parent.
+    if sanitized.chars().all(|c| c == '.') {
+        sanitized = sanitized.replace('.', "_");
+        if sanitized.is_empty() {
+            sanitized.push('_');
+        }
+    }
+    sanitized
+}
+
+/// Creates the `.part` file of `name`, or of `name_1`, `name_2`... if a finished or `.part`
+/// file with that name and `extension` already exists, and returns the name it picked. The
+/// file is created exclusively, so concurrent recordings never share one, and stays locked
+/// while it is open, so that [`recover_dir`](super::recovery::recover_dir) leaves it alone.
+/// Missing parent directories are created.
+pub fn create_unique(name: &str, extension: &str) -> std::io::Result<(String, File)> {
+    if let Some(parent) = Path::new(name).parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    for suffix in 0.. {
+        let candidate = match suffix {
+            0 => name.to_string(),
+            _ => format!("{name}_{suffix}"),
+        };
+        if Path::new(&format!("{candidate}.{extension}")).exists() {
+            continue;
+        }
+        match File::options()
+            .write(true)
+            .create_new(true)
+            .open(format!("{candidate}.{extension}.part"))
+        {
+            Ok(file) => {
+                fs2::FileExt::lock_exclusive(&file)?;
+                return Ok((candidate, file));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("ran out of suffixes for {name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_unique, render_filename, Segment};
+    use crate::downloader::stats::Snapshot;
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[test]
+    fn any_splits_on_first_limit() {
+        let mut segment = Segment::Any(vec![
+            Segment::Time(Duration::from_secs(60), Duration::ZERO),
+            Segment::Size(1000, 0),
+            Segment::MetaChange(false),
+        ]);
+        assert!(!segment.needed(500, Duration::from_secs(30)));
+        assert!(segment.needed(1000, Duration::from_secs(40)));
+        // The size split restarted the time rule at 40s.
+        assert!(!segment.needed(100, Duration::from_secs(90)));
+        assert!(segment.needed(100, Duration::from_secs(100)));
+        segment.meta_changed();
+        assert!(segment.needed(100, Duration::from_secs(101)));
+        assert!(!segment.needed(100, Duration::from_secs(102)));
+    }
+
+    #[test]
+    fn render_template() {
+        let snapshot = Snapshot {
+            files: 2,
+            room: Some("1234".to_string()),
+            title: Some("100% a/b".to_string()),
+            video_codec: Some("H264".to_string()),
+            width: Some(1920),
+            height: Some(1080),
+            ..Default::default()
+        };
+        assert_eq!(
+            render_filename("{room}/{title}_{codec}_{resolution}_{index}{x}", &snapshot),
+            "1234/100% a_b_H264_1920x1080_3{x}"
+        );
+        assert_eq!(render_filename("{title}", &Default::default()), "unknown");
+    }
+
+    #[test]
+    fn render_dot_titles() {
+        let render = |title: &str| {
+            let snapshot = Snapshot {
+                title: Some(title.to_string()),
+                ..Default::default()
+            };
+            render_filename("{title}/x", &snapshot)
+        };
+        assert_eq!(render(".."), "__/x");
+        assert_eq!(render("."), "_/x");
+        assert_eq!(render(""), "_/x");
+        assert_eq!(render("..."), "___/x");
+        assert_eq!(render("a.."), "a../x");
+    }
+
+    #[test]
+    fn unique_names() -> std::io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("stream-gears-{}", std::process::id()));
+        let name = dir.join("a/b").to_str().unwrap().to_string();
+        assert_eq!(create_unique(&name, "flv")?.0, name);
+        assert!(Path::new(&format!("{name}.flv.part")).exists());
+        // Taken by the recording still writing the `.part` file.
+        assert_eq!(create_unique(&name, "flv")?.0, format!("{name}_1"));
+        std::fs::File::create(format!("{name}_2.flv"))?;
+        assert_eq!(create_unique(&name, "flv")?.0, format!("{name}_3"));
+        assert_eq!(create_unique(&name, "ts")?.0, name);
+        std::fs::remove_dir_all(dir)
+    }
+}
@@ -1,5 +1,6 @@
 use nom::Needed;
 use std::io;
+use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -15,6 +16,36 @@ pub enum Error {
 
     #[error("Parsing {0} requires {1:?} bytes/chars.")]
     NomIncomplete(String, Needed),
+
+    #[error("Invalid schedule {0:?}: {1}")]
+    InvalidSchedule(String, String),
+
+    #[error("Free disk space {available} bytes is below the threshold of {threshold} bytes.")]
+    LowDiskSpace { available: u64, threshold: u64 },
+
+    #[error("Recording quota of {0} bytes exceeded.")]
+    QuotaExceeded(u64),
+
+    #[error("file {}", path.display())]
+    File {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("The stream at {0} has ended.")]
+    StreamEnded(String),
+
+    #[error("The recording was stopped.")]
+    Stopped,
+}
+
+impl Error {
+    /// Wraps an I/O error on the file at `path`.
+    pub fn file(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Error::File { path, source }
+    }
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -0,0 +1,206 @@
+//! Exceptions raised to Python, picked from the causes of an error.
+//!
+//! ```text
+//! StreamGearsError
+//! ├── NetworkError
+//! ├── AuthError
+//! │   └── LoginError
+//! │       └── CaptchaRequiredError
+//! ├── StreamEndedError
+//! ├── ParseError
+//! ├── DiskError
+//! └── UploadError
+//! ```
+//!
+//! Every instance has `status` and `url` of the failed HTTP request and `path` of the file
+//! involved, `None` when unknown.
+
+use crate::error::Error;
+use crate::login::{CaptchaRequired, InvalidCredentials, LoginFailed};
+use biliup::error::CustomError;
+use pyo3::prelude::*;
+use std::error::Error as StdError;
+use std::io;
+use std::path::PathBuf;
+
+pub use classes::*;
+
+// pyo3 0.16 expands `create_exception!` to cfgs unknown to newer compilers.
+#[allow(unexpected_cfgs)]
+mod classes {
+    use pyo3::create_exception;
+    use pyo3::exceptions::PyException;
+
+    create_exception!(stream_gears, StreamGearsError, PyException);
+    create_exception!(stream_gears, NetworkError, StreamGearsError);
+    create_exception!(stream_gears, AuthError, StreamGearsError);
+    create_exception!(stream_gears, LoginError, AuthError);
+    create_exception!(stream_gears, CaptchaRequiredError, LoginError);
+    create_exception!(stream_gears, StreamEndedError, StreamGearsError);
+    create_exception!(stream_gears, ParseError, StreamGearsError);
+    create_exception!(stream_gears, DiskError, StreamGearsError);
+    create_exception!(stream_gears, UploadError, StreamGearsError);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Base,
+    Network,
+    Auth,
+    Login,
+    Captcha,
+    StreamEnded,
+    Parse,
+    Disk,
+    Upload,
+}
+
+/// What the causes of an error tell about it.
+#[derive(Default)]
+struct Details {
+    kind: Option<Kind>,
+    status: Option<u16>,
+    url: Option<String>,
+    path: Option<PathBuf>,
+    attrs: Vec<(&'static str, PyObject)>,
+}
+
+impl Details {
+    fn kind(&mut self, kind: Kind) {
+        self.kind.get_or_insert(kind);
+    }
+
+    fn inspect(&mut self, py: Python<'_>, cause: &(dyn StdError + 'static)) {
+        if let Some(e) = cause.downcast_ref::<Error>() {
+            match e {
+                Error::IOError(e) => self.inspect(py, e),
+                Error::ReqwestError(e) => self.inspect(py, e),
+                Error::UrlParseError(_) | Error::NomIncomplete(..) | Error::InvalidSchedule(..) => {
+                    self.kind(Kind::Parse)
+                }
+                Error::LowDiskSpace { .. } | Error::QuotaExceeded(_) => self.kind(Kind::Disk),
+                Error::File { path, source } => {
+                    self.path.get_or_insert_with(|| path.clone());
+                    self.inspect(py, source)
+                }
+                Error::StreamEnded(url) => {
+                    self.url.get_or_insert_with(|| url.clone());
+                    self.kind(Kind::StreamEnded)
+                }
+                Error::Stopped => {}
+            }
+        } else if let Some(e) = cause.downcast_ref::<CustomError>() {
+            match e {
+                CustomError::IO(e) => self.inspect(py, e),
+                CustomError::Reqwest(e) => self.inspect(py, e),
+                CustomError::ReqwestMiddleware(_) => self.kind(Kind::Network),
+                CustomError::SerdeJson(_) | CustomError::SerdeYaml(_) => self.kind(Kind::Parse),
+                CustomError::Other(e) => e.chain().for_each(|cause| self.inspect(py, cause)),
+                _ => {}
+            }
+        } else if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            if let Some(status) = e.status() {
+                self.status.get_or_insert(status.as_u16());
+            }
+            if let Some(url) = e.url() {
+                self.url.get_or_insert_with(|| url.to_string());
+            }
+            self.kind(if e.is_decode() {
+                Kind::Parse
+            } else {
+                Kind::Network
+            });
+        } else if let Some(e) = cause.downcast_ref::<io::Error>() {
+            match e.get_ref() {
+                // Reading a response body reports network errors as I/O errors.
+                Some(inner) if inner.is::<reqwest::Error>() => self.inspect(py, inner),
+                _ if e.kind() == io::ErrorKind::StorageFull => self.kind(Kind::Disk),
+                _ => {}
+            }
+        } else if cause.is::<serde_json::Error>() || cause.is::<url::ParseError>() {
+            self.kind(Kind::Parse)
+        } else if let Some(e) = cause.downcast_ref::<InvalidCredentials>() {
+            self.path.get_or_insert_with(|| e.path.clone());
+            self.kind(Kind::Auth)
+        } else if let Some(e) = cause.downcast_ref::<LoginFailed>() {
+            self.attrs.push(("code", e.code.to_object(py)));
+            self.attrs.push(("message", e.message.to_object(py)));
+            self.kind(Kind::Login)
+        } else if let Some(e) = cause.downcast_ref::<CaptchaRequired>() {
+            self.url.get_or_insert_with(|| e.url.clone());
+            self.attrs
+                .push(("recaptcha_token", e.recaptcha_token.to_object(py)));
+            self.attrs.push(("gee_gt", e.gee_gt.to_object(py)));
+            self.attrs
+                .push(("gee_challenge", e.gee_challenge.to_object(py)));
+            self.kind(Kind::Captcha)
+        }
+    }
+}
+
+fn to_exception(err: anyhow::Error, default: Kind) -> PyErr {
+    Python::with_gil(|py| {
+        let mut details = Details::default();
+        // Like `{err:#}`, without the causes that some errors already print themselves.
+        let mut message = String::new();
+        for cause in err.chain() {
+            details.inspect(py, cause);
+            let text = cause.to_string();
+            if !message.contains(&text) {
+                if !message.is_empty() {
+                    message.push_str(": ");
+                }
+                message.push_str(&text);
+            }
+        }
+        let py_err = match details.kind.unwrap_or(default) {
+            Kind::Base => StreamGearsError::new_err(message),
+            Kind::Network => NetworkError::new_err(message),
+            Kind::Auth => AuthError::new_err(message),
+            Kind::Login => LoginError::new_err(message),
+            Kind::Captcha => CaptchaRequiredError::new_err(message),
+            Kind::StreamEnded => StreamEndedError::new_err(message),
+            Kind::Parse => ParseError::new_err(message),
+            Kind::Disk => DiskError::new_err(message),
+            Kind::Upload => UploadError::new_err(message),
+        };
+        let attrs = [
+            ("status", details.status.to_object(py)),
+            ("url", details.url.to_object(py)),
+            ("path", details.path.to_object(py)),
+        ];
+        for (name, value) in attrs.into_iter().chain(details.attrs) {
+            if let Err(e) = py_err.value(py).setattr(name, value) {
+                return e;
+            }
+        }
+        py_err
+    })
+}
+
+/// Raises `err` as the most specific exception its causes allow, `StreamGearsError`
+/// otherwise.
+pub fn to_py_err(err: anyhow::Error) -> PyErr {
+    to_exception(err, Kind::Base)
+}
+
+/// Like [`to_py_err`], falling back to `UploadError` for failures of an upload.
+pub fn upload_error(err: anyhow::Error) -> PyErr {
+    to_exception(err, Kind::Upload)
+}
+
+pub fn register(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add("StreamGearsError", py.get_type::<StreamGearsError>())?;
+    m.add("NetworkError", py.get_type::<NetworkError>())?;
+    m.add("AuthError", py.get_type::<AuthError>())?;
+    m.add("LoginError", py.get_type::<LoginError>())?;
+    m.add(
+        "CaptchaRequiredError",
+        py.get_type::<CaptchaRequiredError>(),
+    )?;
+    m.add("StreamEndedError", py.get_type::<StreamEndedError>())?;
+    m.add("ParseError", py.get_type::<ParseError>())?;
+    m.add("DiskError", py.get_type::<DiskError>())?;
+    m.add("UploadError", py.get_type::<UploadError>())?;
+    Ok(())
+}
@@ -82,7 +82,7 @@ pub fn tag_header(input: &[u8]) -> IResult<&[u8], TagHeader> {
     )(input)
 }
 
-pub fn complete_tag(input: &[u8]) -> IResult<&[u8], Tag> {
+pub fn complete_tag(input: &[u8]) -> IResult<&[u8], Tag<'_>> {
     flat_map(pair(tag_type, be_u24), |(tag_type, data_size)| {
         map(
             tuple((
@@ -179,7 +179,7 @@ pub struct AACAudioPacket<'a> {
     pub aac_data: &'a [u8],
 }
 
-pub fn aac_audio_packet(input: &[u8], size: usize) -> IResult<&[u8], AACAudioPacket> {
+pub fn aac_audio_packet(input: &[u8], size: usize) -> IResult<&[u8], AACAudioPacket<'_>> {
     if input.len() < size {
         return Err(Err::Incomplete(Needed::new(size)));
     }
@@ -212,7 +212,7 @@ pub struct AudioData<'a> {
     pub sound_data: &'a [u8],
 }
 
-pub fn audio_data(input: &[u8], size: usize) -> IResult<&[u8], AudioData> {
+pub fn audio_data(input: &[u8], size: usize) -> IResult<&[u8], AudioData<'_>> {
     if input.len() < size {
         return Err(Err::Incomplete(Needed::new(size)));
     }
@@ -398,7 +398,7 @@ pub struct AVCVideoPacket<'a> {
     pub avc_data: &'a [u8],
 }
 
-pub fn avc_video_packet(input: &[u8], size: usize) -> IResult<&[u8], AVCVideoPacket> {
+pub fn avc_video_packet(input: &[u8], size: usize) -> IResult<&[u8], AVCVideoPacket<'_>> {
     if input.len() < size {
         return Err(Err::Incomplete(Needed::new(size)));
     }
@@ -425,7 +425,7 @@ pub struct VideoData<'a> {
     pub video_data: &'a [u8],
 }
 
-pub fn video_data(input: &[u8], size: usize) -> IResult<&[u8], VideoData> {
+pub fn video_data(input: &[u8], size: usize) -> IResult<&[u8], VideoData<'_>> {
     if input.len() < size {
         return Err(Err::Incomplete(Needed::new(size)));
     }
@@ -549,7 +549,7 @@ pub struct ScriptDataDate {
 #[allow(non_upper_case_globals)]
 static script_data_name_tag: &[u8] = &[2];
 
-pub fn script_data(input: &[u8]) -> IResult<&[u8], ScriptData> {
+pub fn script_data(input: &[u8]) -> IResult<&[u8], ScriptData<'_>> {
     // Must start with a string, i.e. 2
     map(
         tuple((
@@ -561,7 +561,7 @@ pub fn script_data(input: &[u8]) -> IResult<&[u8], ScriptData> {
     )(input)
 }
 
-pub fn script_data_value(input: &[u8]) -> IResult<&[u8], ScriptDataValue> {
+pub fn script_data_value(input: &[u8]) -> IResult<&[u8], ScriptDataValue<'_>> {
     be_u8(input).and_then(|v| match v {
         (i, 0) => map(be_f64, ScriptDataValue::Number)(i),
         (i, 1) => map(be_u8, |n| ScriptDataValue::Boolean(n != 0))(i),
@@ -579,11 +579,11 @@ pub fn script_data_value(input: &[u8]) -> IResult<&[u8], ScriptDataValue> {
     })
 }
 
-pub fn script_data_objects(input: &[u8]) -> IResult<&[u8], Vec<ScriptDataObject>> {
+pub fn script_data_objects(input: &[u8]) -> IResult<&[u8], Vec<ScriptDataObject<'_>>> {
     terminated(many0(script_data_object), script_data_object_end)(input)
 }
 
-pub fn script_data_object(input: &[u8]) -> IResult<&[u8], ScriptDataObject> {
+pub fn script_data_object(input: &[u8]) -> IResult<&[u8], ScriptDataObject<'_>> {
     map(
         pair(script_data_string, script_data_value),
         |(name, data)| ScriptDataObject { name, data },
@@ -615,12 +615,230 @@ pub fn script_data_date(input: &[u8]) -> IResult<&[u8], ScriptDataDate> {
     )(input)
 }
 
-pub fn script_data_ecma_array(input: &[u8]) -> IResult<&[u8], Vec<ScriptDataObject>> {
+pub fn script_data_ecma_array(input: &[u8]) -> IResult<&[u8], Vec<ScriptDataObject<'_>>> {
     map(pair(be_u32, script_data_objects), |(_, data_objects)| {
         data_objects
     })(input)
 }
 
-pub fn script_data_strict_array(input: &[u8]) -> IResult<&[u8], Vec<ScriptDataValue>> {
+pub fn script_data_strict_array(input: &[u8]) -> IResult<&[u8], Vec<ScriptDataValue<'_>>> {
     flat_map(be_u32, |o| many_m_n(1, o as usize, script_data_value))(input)
 }
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AVCDecoderConfigurationRecord<'a> {
+    pub configuration_version: u8,
+    pub profile_indication: u8,
+    pub profile_compatibility: u8,
+    pub level_indication: u8,
+    pub length_size_minus_one: u8,
+    pub sequence_parameter_sets: Vec<&'a [u8]>,
+    pub picture_parameter_sets: Vec<&'a [u8]>,
+}
+
+/// Parses the body of an AVC sequence header, i.e. what follows
+/// [`avc_video_packet_header`] when the packet type is `SequenceHeader`.
+pub fn avc_decoder_configuration_record(
+    input: &[u8],
+) -> IResult<&[u8], AVCDecoderConfigurationRecord<'_>> {
+    map(
+        tuple((
+            be_u8,
+            be_u8,
+            be_u8,
+            be_u8,
+            be_u8,
+            flat_map(be_u8, |n| {
+                many_m_n(
+                    (n & 0x1f) as usize,
+                    (n & 0x1f) as usize,
+                    length_data(be_u16),
+                )
+            }),
+            flat_map(be_u8, |n| {
+                many_m_n(n as usize, n as usize, length_data(be_u16))
+            }),
+        )),
+        |(
+            configuration_version,
+            profile_indication,
+            profile_compatibility,
+            level_indication,
+            length_size_minus_one,
+            sequence_parameter_sets,
+            picture_parameter_sets,
+        )| AVCDecoderConfigurationRecord {
+            configuration_version,
+            profile_indication,
+            profile_compatibility,
+            level_indication,
+            length_size_minus_one: length_size_minus_one & 0x03,
+            sequence_parameter_sets,
+            picture_parameter_sets,
+        },
+    )(input)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AudioSpecificConfig {
+    pub audio_object_type: u8,
+    pub sampling_frequency_index: u8,
+    pub channel_configuration: u8,
+}
+
+impl AudioSpecificConfig {
+    pub fn sampling_frequency(&self) -> Option<u32> {
+        const FREQUENCIES: [u32; 13] = [
+            96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+        ];
+        FREQUENCIES
+            .get(self.sampling_frequency_index as usize)
+            .copied()
+    }
+}
+
+/// Parses the body of an AAC sequence header, i.e. what follows
+/// [`aac_audio_packet_header`] when the packet type is `SequenceHeader`.
+pub fn audio_specific_config(input: &[u8]) -> IResult<&[u8], AudioSpecificConfig> {
+    let take_bits = tuple((take(5usize), take(4usize), take(4usize)));
+    map(
+        bits::<_, _, Error<_>, _, _>(take_bits),
+        |(audio_object_type, sampling_frequency_index, channel_configuration)| {
+            AudioSpecificConfig {
+                audio_object_type,
+                sampling_frequency_index,
+                channel_configuration,
+            }
+        },
+    )(input)
+}
+
+/// Extracts the picture size in pixels from an H.264 sequence parameter set NAL unit.
+pub fn sps_resolution(sps: &[u8]) -> Option<(u32, u32)> {
+    // Strip emulation prevention bytes (00 00 03) before reading the RBSP.
+    let mut rbsp = Vec::with_capacity(sps.len());
+    let mut zeros = 0;
+    for &b in sps.get(1..)? {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        rbsp.push(b);
+    }
+    let mut r = BitReader::new(&rbsp);
+    let profile_idc = r.read_bits(8)?;
+    r.read_bits(16)?; // constraint flags, level_idc
+    r.read_ue()?; // seq_parameter_set_id
+    let mut chroma_format_idc = 1;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = r.read_ue()?;
+        if chroma_format_idc == 3 {
+            r.read_bits(1)?; // separate_colour_plane_flag
+        }
+        r.read_ue()?; // bit_depth_luma_minus8
+        r.read_ue()?; // bit_depth_chroma_minus8
+        r.read_bits(1)?; // qpprime_y_zero_transform_bypass_flag
+        if r.read_bits(1)? == 1 {
+            let count = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..count {
+                if r.read_bits(1)? == 1 {
+                    let size = if i < 6 { 16 } else { 64 };
+                    let mut last_scale = 8i64;
+                    let mut next_scale = 8i64;
+                    for _ in 0..size {
+                        if next_scale != 0 {
+                            next_scale = (last_scale + r.read_se()? + 256) % 256;
+                        }
+                        if next_scale != 0 {
+                            last_scale = next_scale;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    r.read_ue()?; // log2_max_frame_num_minus4
+    match r.read_ue()? {
+        0 => {
+            r.read_ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            r.read_bits(1)?; // delta_pic_order_always_zero_flag
+            r.read_se()?; // offset_for_non_ref_pic
+            r.read_se()?; // offset_for_top_to_bottom_field
+            for _ in 0..r.read_ue()? {
+                r.read_se()?;
+            }
+        }
+        _ => {}
+    }
+    r.read_ue()?; // max_num_ref_frames
+    r.read_bits(1)?; // gaps_in_frame_num_value_allowed_flag
+    let width_in_mbs = r.read_ue()? + 1;
+    let height_in_map_units = r.read_ue()? + 1;
+    let frame_mbs_only = r.read_bits(1)?;
+    if frame_mbs_only == 0 {
+        r.read_bits(1)?; // mb_adaptive_frame_field_flag
+    }
+    r.read_bits(1)?; // direct_8x8_inference_flag
+    let (mut left, mut right, mut top, mut bottom) = (0, 0, 0, 0);
+    if r.read_bits(1)? == 1 {
+        left = r.read_ue()?;
+        right = r.read_ue()?;
+        top = r.read_ue()?;
+        bottom = r.read_ue()?;
+    }
+    let (sub_width, sub_height) = match chroma_format_idc {
+        1 => (2, 2),
+        2 => (2, 1),
+        _ => (1, 1),
+    };
+    let crop_unit_x = sub_width;
+    let crop_unit_y = sub_height * (2 - frame_mbs_only);
+    let width = (width_in_mbs * 16).checked_sub((left + right) * crop_unit_x)?;
+    let height = ((2 - frame_mbs_only) * height_in_map_units * 16)
+        .checked_sub((top + bottom) * crop_unit_y)?;
+    Some((width, height))
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn read_bits(&mut self, n: usize) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..n {
+            let byte = self.data.get(self.pos / 8)?;
+            let bit = (byte >> (7 - self.pos % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.pos += 1;
+        }
+        Some(value)
+    }
+
+    fn read_ue(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while self.read_bits(1)? == 0 {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << leading_zeros) - 1 + self.read_bits(leading_zeros)?)
+    }
+
+    fn read_se(&mut self) -> Option<i64> {
+        let k = self.read_ue()? as i64;
+        Some(if k % 2 == 1 { (k + 1) / 2 } else { -(k / 2) })
+    }
+}
@@ -25,9 +25,13 @@ pub struct FlvFile {
 
 impl FlvFile {
     pub fn new(file_name: &str) -> std::io::Result<Self> {
-        let file_name = util::format_filename(file_name);
-        let out =
-            File::create(format!("{file_name}.flv.part")).expect("Unable to create flv file.");
+        Self::create(&util::format_filename(file_name))
+    }
+
+    /// Creates `{file_name}.flv.part` without any further formatting of the name,
+    /// appending a suffix if the file already exists.
+    pub fn create(file_name: &str) -> std::io::Result<Self> {
+        let (file_name, out) = util::create_unique(file_name, "flv")?;
         let mut buf_writer = BufWriter::new(out);
         buf_writer.write_all(&FLV_HEADER)?;
         Self::write_previous_tag_size(&mut buf_writer, 0)?;
@@ -69,6 +73,8 @@ impl FlvFile {
 
 impl Drop for FlvFile {
     fn drop(&mut self) {
+        // Readers tailing the file take the rename as the end of the recording.
+        self.buf_writer.flush().unwrap_or_else(|e| error!("{e}"));
         std::fs::rename(
             format!("{}.flv.part", self.name),
             format!("{}.flv", self.name),
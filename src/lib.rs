@@ -1,24 +1,57 @@
 pub mod downloader;
 pub mod error;
+pub mod exceptions;
 pub mod flv_parser;
 pub mod flv_writer;
-mod uploader;
+pub mod logging;
 mod login;
+mod runtime;
+pub mod uploader;
 
 use crate::downloader::construct_headers;
+use crate::exceptions::{to_py_err, upload_error};
+use crate::login::store::{Account, AccountStore};
+use crate::login::{Credentials, QrLogin};
+use crate::runtime::block_on_py;
+use crate::uploader::edit::Submission;
+use crate::uploader::limiter::UPLOAD_LIMITER;
+use crate::uploader::live::LiveUpload;
+use crate::uploader::meta::StudioMeta;
+use crate::uploader::probe::{self, LineProbe, LINE_CACHE};
+use crate::uploader::progress::{OnComplete, OnProgress, Progress, Summary};
+use crate::uploader::queue::{Job, PyUploadQueue};
+use crate::uploader::retry::RetryPolicy;
 use crate::uploader::UploadLine;
 
 use pyo3::prelude::*;
+use pyo3::types::PyTuple;
 
+use downloader::guard::{DiskGuard, Quota, QuotaPolicy};
+use downloader::schedule::Schedule;
+use downloader::stats::{Snapshot, Stats};
 use downloader::util::Segment;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::time::Duration;
 
-use tracing_subscriber::layer::SubscriberExt;
+use tracing::Instrument;
 
+/// How to split a recording: a `Segment`, or a dict or any object with the keys or
+/// attributes `any`, `time` and/or `size`, `cron` or `meta_change`.
 #[derive(FromPyObject)]
 pub enum PySegment {
+    Rule(SegmentRule),
+    Any {
+        #[pyo3(attribute("any"))]
+        any: Vec<PySegment>,
+    },
+    /// Splits on whichever of `time` or `size` is hit first.
+    TimeOrSize {
+        #[pyo3(attribute("time"))]
+        time: u64,
+        #[pyo3(attribute("size"))]
+        size: u64,
+    },
     Time {
         #[pyo3(attribute("time"))]
         time: u64,
@@ -27,207 +60,851 @@ pub enum PySegment {
         #[pyo3(attribute("size"))]
         size: u64,
     },
+    Cron {
+        #[pyo3(attribute("cron"))]
+        cron: String,
+    },
+    MetaChange {
+        #[pyo3(attribute("meta_change"))]
+        meta_change: bool,
+    },
+    AnyItem {
+        #[pyo3(item("any"))]
+        any: Vec<PySegment>,
+    },
+    TimeOrSizeItem {
+        #[pyo3(item("time"))]
+        time: u64,
+        #[pyo3(item("size"))]
+        size: u64,
+    },
+    TimeItem {
+        #[pyo3(item("time"))]
+        time: u64,
+    },
+    SizeItem {
+        #[pyo3(item("size"))]
+        size: u64,
+    },
+    CronItem {
+        #[pyo3(item("cron"))]
+        cron: String,
+    },
+    MetaChangeItem {
+        #[pyo3(item("meta_change"))]
+        meta_change: bool,
+    },
 }
 
-#[pyfunction]
+impl TryFrom<PySegment> for Segment {
+    type Error = PyErr;
+
+    fn try_from(segment: PySegment) -> PyResult<Self> {
+        Ok(match segment {
+            PySegment::Rule(rule) => rule.segment,
+            PySegment::Any { any } | PySegment::AnyItem { any } => Segment::Any(
+                any.into_iter()
+                    .map(Segment::try_from)
+                    .collect::<PyResult<_>>()?,
+            ),
+            PySegment::TimeOrSize { time, size } | PySegment::TimeOrSizeItem { time, size } => {
+                Segment::Any(vec![
+                    Segment::Time(Duration::from_secs(time), Duration::default()),
+                    Segment::Size(size, 0),
+                ])
+            }
+            PySegment::Time { time } | PySegment::TimeItem { time } => {
+                Segment::Time(Duration::from_secs(time), Duration::default())
+            }
+            PySegment::Size { size } | PySegment::SizeItem { size } => Segment::Size(size, 0),
+            PySegment::Cron { cron } | PySegment::CronItem { cron } => {
+                Segment::Schedule(schedule(&cron)?, None)
+            }
+            PySegment::MetaChange { meta_change: true }
+            | PySegment::MetaChangeItem { meta_change: true } => Segment::MetaChange(false),
+            PySegment::MetaChange { meta_change: false }
+            | PySegment::MetaChangeItem { meta_change: false } => {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "meta_change must be True when set",
+                ))
+            }
+        })
+    }
+}
+
+/// Without a `segment`, the recording goes into a single file.
+fn segment(segment: Option<PySegment>) -> PyResult<Segment> {
+    segment.map_or_else(|| Ok(Segment::Any(Vec::new())), Segment::try_from)
+}
+
+fn schedule(cron: &str) -> PyResult<Schedule> {
+    cron.parse()
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("{e}")))
+}
+
+/// A rule for splitting a recording into files, built by its static methods.
+#[pyclass(name = "Segment")]
+#[derive(Clone)]
+pub struct SegmentRule {
+    segment: Segment,
+    repr: String,
+}
+
+#[pymethods]
+impl SegmentRule {
+    /// Splits after `secs` seconds of media.
+    #[staticmethod]
+    fn time(secs: u64) -> Self {
+        Self {
+            segment: Segment::Time(Duration::from_secs(secs), Duration::default()),
+            repr: format!("Segment.time({secs})"),
+        }
+    }
+
+    /// Splits after `bytes` bytes.
+    #[staticmethod]
+    fn size(bytes: u64) -> Self {
+        Self {
+            segment: Segment::Size(bytes, 0),
+            repr: format!("Segment.size({bytes})"),
+        }
+    }
+
+    /// Splits whenever the cron expression `expr` fires, e.g. `"0 * * * *"` every hour.
+    #[staticmethod]
+    fn cron(expr: &str) -> PyResult<Self> {
+        Ok(Self {
+            segment: Segment::Schedule(schedule(expr)?, None),
+            repr: format!("Segment.cron({expr:?})"),
+        })
+    }
+
+    /// Splits when onMetaData or the title set on the `Downloader` changes.
+    #[staticmethod]
+    fn meta_change() -> Self {
+        Self {
+            segment: Segment::MetaChange(false),
+            repr: "Segment.meta_change()".to_string(),
+        }
+    }
+
+    /// Splits as soon as any of `segments` is hit.
+    #[staticmethod]
+    #[args(segments = "*")]
+    fn any(segments: &PyTuple) -> PyResult<Self> {
+        let mut any = Vec::with_capacity(segments.len());
+        let mut reprs = Vec::with_capacity(segments.len());
+        for segment in segments {
+            any.push(segment.extract::<PySegment>()?.try_into()?);
+            reprs.push(segment.repr()?.to_string());
+        }
+        Ok(Self {
+            segment: Segment::Any(any),
+            repr: format!("Segment.any({})", reprs.join(", ")),
+        })
+    }
+
+    fn __repr__(&self) -> &str {
+        &self.repr
+    }
+}
+
+/// Records `url` to `file_name`, a template, see `downloader::util::render_filename`. It is
+/// split into files according to `segment`, or not at all without one.
+#[pyfunction(segment = "None")]
 fn download(
     py: Python<'_>,
     url: &str,
     header_map: HashMap<String, String>,
     file_name: &str,
-    segment: PySegment,
+    segment: Option<PySegment>,
 ) -> PyResult<()> {
+    let segment = crate::segment(segment)?;
     py.allow_threads(|| {
-        let map = construct_headers(header_map);
-        // 输出到控制台中
-        let formatting_layer = tracing_subscriber::FmtSubscriber::builder()
-            // will be written to stdout.
-            // builds the subscriber.
-            .finish();
-        let file_appender = tracing_appender::rolling::never("", "download.log");
-        let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
-        let file_layer = tracing_subscriber::fmt::layer()
-            .with_ansi(false)
-            .with_writer(non_blocking);
-
-        let collector = formatting_layer.with(file_layer);
-        let segment = match segment {
-            PySegment::Time { time } => {
-                Segment::Time(Duration::from_secs(time), Duration::default())
-            }
-            PySegment::Size { size } => Segment::Size(size, 0),
-        };
-        tracing::subscriber::with_default(collector, || -> PyResult<()> {
-            match downloader::download(url, map, file_name, segment) {
-                Ok(res) => Ok(res),
-                // Ok(_) => {  },
-                Err(err) => {
-                    return Err(pyo3::exceptions::PyRuntimeError::new_err(format!(
-                        "{}, {}",
-                        err.root_cause(),
-                        err
-                    )));
-                }
-            }
-        })
+        download_with_stats(
+            url,
+            header_map,
+            file_name,
+            segment,
+            &Stats::new(),
+            Default::default(),
+        )
     })
+    .map_err(to_py_err)
 }
-#[pyfunction]
-fn login_by_cookies()->PyResult<bool>{
-    let  rt = tokio::runtime::Runtime::new().unwrap();
-    let  result =rt.block_on(async {
-         login::login_by_cookies().await
-    });
-    match result{
-        Ok(_) => Ok(true),
-        Err(err) => {
-            return Err(pyo3::exceptions::PyRuntimeError::new_err(format!(
-                "{}, {}",
-                err.root_cause(),
-                err
-            )));
+
+/// Awaitable `download`. The recording runs on a thread of the shared runtime, cancelling
+/// finishes the current file and stops it.
+#[pyfunction(segment = "None")]
+fn download_async(
+    py: Python<'_>,
+    url: String,
+    header_map: HashMap<String, String>,
+    file_name: String,
+    segment: Option<PySegment>,
+) -> PyResult<&PyAny> {
+    let segment = crate::segment(segment)?;
+    spawn_download(
+        py,
+        url,
+        header_map,
+        file_name,
+        segment,
+        Stats::new(),
+        Default::default(),
+    )
+}
+
+/// Stops the recording of `Stats` when dropped before it finished.
+struct StopOnDrop(Option<Stats>);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        if let Some(stats) = self.0.take() {
+            stats.stop()
         }
     }
+}
 
+fn spawn_download(
+    py: Python<'_>,
+    url: String,
+    header_map: HashMap<String, String>,
+    file_name: String,
+    segment: Segment,
+    stats: Stats,
+    guard: DiskGuard,
+) -> PyResult<&PyAny> {
+    let mut stop = StopOnDrop(Some(stats.clone()));
+    runtime::future_into_py(py, async move {
+        let recording = tokio::task::spawn_blocking(move || {
+            download_with_stats(&url, header_map, &file_name, segment, &stats, guard)
+        });
+        let result = recording
+            .await
+            .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
+        stop.0.take();
+        // Converted here, a recording finishing after the interpreter exited has no Python.
+        result.map_err(to_py_err)
+    })
+}
 
+fn download_with_stats(
+    url: &str,
+    header_map: HashMap<String, String>,
+    file_name: &str,
+    segment: Segment,
+    stats: &Stats,
+    guard: DiskGuard,
+) -> anyhow::Result<()> {
+    let map = construct_headers(header_map);
+    logging::init();
+    downloader::download(url, map, file_name, segment, stats, guard)
 }
-#[pyfunction]
- fn send_sms(country_code:u32,phone:u64) -> PyResult<String> {
-    let  rt = tokio::runtime::Runtime::new().unwrap();
-    let  result= rt.block_on(async {
-       login::send_sms(country_code,phone).await
-    });
-    match result{
-        Ok(res)=>{
-            Ok(res.to_string())
+
+/// A recording whose statistics can be read from another thread while `run` is blocking.
+#[pyclass]
+struct Downloader {
+    url: String,
+    header_map: HashMap<String, String>,
+    file_name: String,
+    segment: Segment,
+    stats: Stats,
+    guard: DiskGuard,
+}
+
+// pyo3 0.16 nests the `#[new]` wrapper in a static, outside the scope of an `allow` on the
+// impl itself.
+#[allow(non_local_definitions)]
+const _: () = {
+    #[pymethods]
+    impl Downloader {
+        /// `file_name` is a template, see `downloader::util::render_filename`, split according
+        /// to `segment` or not at all without one.
+        ///
+        /// The recording stops once less than `min_free_space` bytes are left on the output
+        /// volume, checked every `check_interval` seconds and on every split. `quota` limits the
+        /// bytes kept on disk, `quota_policy` is either `"stop"` or `"delete_oldest"`.
+        #[new]
+        #[args(
+            segment = "None",
+            room = "None",
+            min_free_space = "0",
+            check_interval = "10",
+            quota = "None",
+            quota_policy = "\"stop\""
+        )]
+        #[allow(clippy::too_many_arguments)]
+        fn new(
+            url: String,
+            header_map: HashMap<String, String>,
+            file_name: String,
+            segment: Option<PySegment>,
+            room: Option<String>,
+            min_free_space: u64,
+            check_interval: u64,
+            quota: Option<u64>,
+            quota_policy: &str,
+        ) -> PyResult<Self> {
+            let stats = Stats::new();
+            if let Some(room) = room {
+                stats.set_room(&room);
+            }
+            let policy = match quota_policy {
+                "stop" => QuotaPolicy::Stop,
+                "delete_oldest" => QuotaPolicy::DeleteOldest,
+                _ => {
+                    return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "unknown quota policy {quota_policy:?}"
+                    )))
+                }
+            };
+            let quota = quota.map(|max_bytes| Quota { max_bytes, policy });
+            Ok(Self {
+                url,
+                header_map,
+                file_name,
+                segment: crate::segment(segment)?,
+                stats,
+                guard: DiskGuard::new(min_free_space, Duration::from_secs(check_interval), quota),
+            })
+        }
+
+        fn run(&self, py: Python<'_>) -> PyResult<()> {
+            let header_map = self.header_map.clone();
+            let segment = self.segment.clone();
+            let guard = self.guard.clone();
+            py.allow_threads(|| {
+                download_with_stats(
+                    &self.url,
+                    header_map,
+                    &self.file_name,
+                    segment,
+                    &self.stats,
+                    guard,
+                )
+            })
+            .map_err(to_py_err)
         }
-        Err(err)=>{
-            Err(pyo3::exceptions::PyRuntimeError::new_err(format!("{}",err)))
+
+        /// Awaitable `run`, cancelling it stops the recording like `stop`.
+        fn run_async<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+            spawn_download(
+                py,
+                self.url.clone(),
+                self.header_map.clone(),
+                self.file_name.clone(),
+                self.segment.clone(),
+                self.stats.clone(),
+                self.guard.clone(),
+            )
+        }
+
+        /// Finishes the current file and makes `run` return once the next tag or HLS segment
+        /// arrives.
+        fn stop(&self) {
+            self.stats.stop()
+        }
+
+        fn stats(&self) -> Snapshot {
+            self.stats.snapshot()
+        }
+
+        /// Reports the current stream title, a change splits the file when the segment has
+        /// `meta_change` set.
+        fn set_title(&self, title: &str) {
+            self.stats.set_title(title)
         }
     }
+};
+
+/// Finalizes the `.part` files a killed recording left in `dir`, returns the recovered paths.
+/// Files of recordings still running in `dir` are skipped.
+#[pyfunction(rewrite_metadata = "true")]
+fn recover(py: Python<'_>, dir: PathBuf, rewrite_metadata: bool) -> PyResult<Vec<PathBuf>> {
+    py.allow_threads(
+        || match downloader::recovery::recover_dir(&dir, rewrite_metadata) {
+            Ok(recovered) => Ok(recovered.into_iter().map(|r| r.path).collect()),
+            Err(err) => Err(to_py_err(err.into())),
+        },
+    )
 }
-#[pyfunction]
- fn login_by_sms(code:u32,  ret:String) -> PyResult<bool>{
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let  result= rt.block_on(async {
-        login::login_by_sms(code,serde_json::from_str(&ret).unwrap()).await
-    });
-    match result
-    {
-        Ok(_)=>Ok(true),
-        Err(_)=>Ok(false),
-    }
+
+#[pyfunction(path = "PathBuf::from(\"cookies.json\")")]
+fn login_by_cookies(py: Python<'_>, path: PathBuf) -> PyResult<bool> {
+    py.allow_threads(|| block_on_py(login::login_by_cookies(&path)))?;
+    Ok(true)
+}
+
+/// Awaitable `login_by_cookies`.
+#[pyfunction(path = "PathBuf::from(\"cookies.json\")")]
+fn login_by_cookies_async(py: Python<'_>, path: PathBuf) -> PyResult<&PyAny> {
+    runtime::future_into_py(py, async move {
+        login::login_by_cookies(&path).await.map_err(to_py_err)?;
+        Ok(true)
+    })
+}
+
+/// Takes the captcha solution passed to `send_sms` apart.
+fn captcha(captcha: Option<HashMap<String, String>>) -> PyResult<Option<login::Captcha>> {
+    let Some(mut captcha) = captcha else {
+        return Ok(None);
+    };
+    let mut take = |name: &str| {
+        captcha.remove(name).ok_or_else(|| {
+            pyo3::exceptions::PyValueError::new_err(format!("captcha without {name}"))
+        })
+    };
+    Ok(Some(login::Captcha {
+        recaptcha_token: take("recaptcha_token")?,
+        gee_challenge: take("gee_challenge")?,
+        gee_validate: take("gee_validate")?,
+        gee_seccode: take("gee_seccode")?,
+    }))
+}
+
+/// Sends a login code to `phone`, returns the request to pass to `login_by_sms`.
+///
+/// Raises `CaptchaRequiredError` when a geetest captcha has to be solved first. Its
+/// `gee_gt` and `gee_challenge` start the captcha, call again with `captcha` set to a dict
+/// of its `recaptcha_token` and the `gee_challenge`, `gee_validate` and `gee_seccode` of
+/// the solution.
+#[pyfunction(captcha = "None")]
+fn send_sms(
+    py: Python<'_>,
+    country_code: u32,
+    phone: u64,
+    captcha: Option<HashMap<String, String>>,
+) -> PyResult<String> {
+    let captcha = self::captcha(captcha)?;
+    py.allow_threads(|| block_on_py(login::send_sms(country_code, phone, captcha)))
+        .map(|res| res.to_string())
+}
+
+/// Awaitable `send_sms`.
+#[pyfunction(captcha = "None")]
+fn send_sms_async(
+    py: Python<'_>,
+    country_code: u32,
+    phone: u64,
+    captcha: Option<HashMap<String, String>>,
+) -> PyResult<&PyAny> {
+    let captcha = self::captcha(captcha)?;
+    runtime::future_into_py(py, async move {
+        login::send_sms(country_code, phone, captcha)
+            .await
+            .map(|res| res.to_string())
+            .map_err(to_py_err)
+    })
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(json: &str) -> PyResult<T> {
+    serde_json::from_str(json).map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
 }
+
+/// Logs in with the SMS `code` and the request `send_sms` returned, saving the credentials
+/// to `path`. Raises `LoginError` with the site's message if it refuses.
+#[pyfunction(path = "PathBuf::from(\"cookies.json\")")]
+fn login_by_sms(py: Python<'_>, code: u32, ret: String, path: PathBuf) -> PyResult<bool> {
+    let ret = parse_json(&ret)?;
+    py.allow_threads(|| block_on_py(login::login_by_sms(code, ret, &path)))
+}
+
+/// Awaitable `login_by_sms`.
+#[pyfunction(path = "PathBuf::from(\"cookies.json\")")]
+fn login_by_sms_async(py: Python<'_>, code: u32, ret: String, path: PathBuf) -> PyResult<&PyAny> {
+    let ret = parse_json(&ret)?;
+    runtime::future_into_py(py, async move {
+        login::login_by_sms(code, ret, &path)
+            .await
+            .map_err(to_py_err)
+    })
+}
+
 #[pyfunction]
-fn get_qrcode()->PyResult<String>{
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let  result= rt.block_on(async {
-        login::get_qrcode().await
-    });
-    match result{
-        Ok(res)=>{
-            Ok(res.to_string())
-        }
-        Err(err)=>{
-            Err(pyo3::exceptions::PyRuntimeError::new_err(format!("{}",err)))
-        }
-    }
+fn get_qrcode(py: Python<'_>) -> PyResult<String> {
+    py.allow_threads(|| block_on_py(login::get_qrcode()))
+        .map(|res| res.to_string())
 }
+
+/// Awaitable `get_qrcode`.
 #[pyfunction]
-fn login_by_qrcode(ret:String) -> PyResult<bool>{
-    let  rt = tokio::runtime::Runtime::new().unwrap();
-    let result= rt.block_on(async {
-        login::login_by_qrcode(serde_json::from_str(&ret).unwrap()).await
-    });
-    match result
-    {
-        Ok(_)=>Ok(true),
-        Err(err)=>{
-            Err(pyo3::exceptions::PyRuntimeError::new_err(format!("{}",err)))
-        },
-    }
+fn get_qrcode_async(py: Python<'_>) -> PyResult<&PyAny> {
+    runtime::future_into_py(py, async move {
+        login::get_qrcode()
+            .await
+            .map(|res| res.to_string())
+            .map_err(to_py_err)
+    })
+}
 
+#[pyfunction(path = "PathBuf::from(\"cookies.json\")")]
+fn login_by_qrcode(py: Python<'_>, ret: String, path: PathBuf) -> PyResult<bool> {
+    let ret = parse_json(&ret)?;
+    py.allow_threads(|| block_on_py(login::login_by_qrcode(ret, &path)))?;
+    Ok(true)
 }
 
-#[pyfunction]
+/// Awaitable `login_by_qrcode`.
+#[pyfunction(path = "PathBuf::from(\"cookies.json\")")]
+fn login_by_qrcode_async(py: Python<'_>, ret: String, path: PathBuf) -> PyResult<&PyAny> {
+    let ret = parse_json(&ret)?;
+    runtime::future_into_py(py, async move {
+        login::login_by_qrcode(ret, &path)
+            .await
+            .map_err(to_py_err)?;
+        Ok(true)
+    })
+}
+
+/// Reads the account stored in the credential file at `path`.
+#[pyfunction(path = "PathBuf::from(\"cookies.json\")")]
+fn load_credentials(py: Python<'_>, path: PathBuf) -> PyResult<Credentials> {
+    py.allow_threads(|| block_on_py(login::load_credentials(&path)))
+}
+
+/// Awaitable `load_credentials`.
+#[pyfunction(path = "PathBuf::from(\"cookies.json\")")]
+fn load_credentials_async(py: Python<'_>, path: PathBuf) -> PyResult<&PyAny> {
+    runtime::future_into_py(py, async move {
+        login::load_credentials(&path).await.map_err(to_py_err)
+    })
+}
+
+/// Checks the credential file at `path` online and refreshes it if the site asks for it.
+/// Raises `AuthError` if the credentials are no longer valid.
+#[pyfunction(path = "PathBuf::from(\"cookies.json\")")]
+fn refresh_credentials(py: Python<'_>, path: PathBuf) -> PyResult<Credentials> {
+    py.allow_threads(|| block_on_py(login::refresh_credentials(&path)))
+}
+
+/// Awaitable `refresh_credentials`.
+#[pyfunction(path = "PathBuf::from(\"cookies.json\")")]
+fn refresh_credentials_async(py: Python<'_>, path: PathBuf) -> PyResult<&PyAny> {
+    runtime::future_into_py(py, async move {
+        login::refresh_credentials(&path).await.map_err(to_py_err)
+    })
+}
+
+/// Uploads `video_path` and submits them, `meta` is a `StudioMeta` or a dict of its keyword
+/// arguments and is validated before anything is uploaded.
+///
+/// Pass `state_file` to make the upload resumable, see `uploader::upload`.
+///
+/// Failed chunks are retried up to `max_attempts` times in total, waiting `retry_backoff`
+/// seconds before the first retry and twice as long before every further one.
+///
+/// `on_progress(file_name, uploaded_bytes, total_bytes)` is called at most every
+/// `progress_interval` seconds per file, `on_complete(summary)` once per uploaded file.
+///
+/// With `cookie_file` set to `None`, the credentials of `account` in the `AccountStore` at
+/// `accounts_dir` are used, or those of its default account.
+#[allow(clippy::too_many_arguments)]
+#[pyfunction(
+    line = "None",
+    limit = "3",
+    state_file = "None",
+    max_attempts = "5",
+    retry_backoff = "1.0",
+    on_progress = "None",
+    on_complete = "None",
+    progress_interval = "1.0",
+    account = "None",
+    accounts_dir = "None"
+)]
 fn upload(
     py: Python<'_>,
     video_path: Vec<PathBuf>,
-    cookie_file: PathBuf,
-    title: String,
-    tid: u16,
-    tag: String,
-    copyright: u8,
-    source: String,
-    desc: String,
-    dynamic: String,
-    cover: String,
-    dtime: Option<u32>,
+    cookie_file: Option<PathBuf>,
+    meta: &PyAny,
     line: Option<UploadLine>,
     limit: usize,
+    state_file: Option<PathBuf>,
+    max_attempts: u32,
+    retry_backoff: f64,
+    on_progress: Option<PyObject>,
+    on_complete: Option<PyObject>,
+    progress_interval: f64,
+    account: Option<String>,
+    accounts_dir: Option<PathBuf>,
 ) -> PyResult<()> {
+    let upload = upload_future(
+        video_path,
+        cookie_file,
+        meta,
+        line,
+        limit,
+        state_file,
+        max_attempts,
+        retry_backoff,
+        on_progress,
+        on_complete,
+        progress_interval,
+        account,
+        accounts_dir,
+    )?;
+    py.allow_threads(|| runtime::block_on(upload))
+}
+
+/// Awaitable `upload`, cancelling it stops the upload.
+#[allow(clippy::too_many_arguments)]
+#[pyfunction(
+    line = "None",
+    limit = "3",
+    state_file = "None",
+    max_attempts = "5",
+    retry_backoff = "1.0",
+    on_progress = "None",
+    on_complete = "None",
+    progress_interval = "1.0",
+    account = "None",
+    accounts_dir = "None"
+)]
+fn upload_async<'py>(
+    py: Python<'py>,
+    video_path: Vec<PathBuf>,
+    cookie_file: Option<PathBuf>,
+    meta: &PyAny,
+    line: Option<UploadLine>,
+    limit: usize,
+    state_file: Option<PathBuf>,
+    max_attempts: u32,
+    retry_backoff: f64,
+    on_progress: Option<PyObject>,
+    on_complete: Option<PyObject>,
+    progress_interval: f64,
+    account: Option<String>,
+    accounts_dir: Option<PathBuf>,
+) -> PyResult<&'py PyAny> {
+    let upload = upload_future(
+        video_path,
+        cookie_file,
+        meta,
+        line,
+        limit,
+        state_file,
+        max_attempts,
+        retry_backoff,
+        on_progress,
+        on_complete,
+        progress_interval,
+        account,
+        accounts_dir,
+    )?;
+    runtime::future_into_py(py, upload)
+}
+
+/// Checks the arguments of `upload` and returns the upload to run.
+#[allow(clippy::too_many_arguments)]
+fn upload_future(
+    video_path: Vec<PathBuf>,
+    cookie_file: Option<PathBuf>,
+    meta: &PyAny,
+    line: Option<UploadLine>,
+    limit: usize,
+    state_file: Option<PathBuf>,
+    max_attempts: u32,
+    retry_backoff: f64,
+    on_progress: Option<PyObject>,
+    on_complete: Option<PyObject>,
+    progress_interval: f64,
+    account: Option<String>,
+    accounts_dir: Option<PathBuf>,
+) -> PyResult<impl std::future::Future<Output = PyResult<()>> + Send + 'static> {
+    let meta = StudioMeta::from_py(meta)?;
+    meta.validate(video_path.len())
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+    let cookie_file = match (cookie_file, account) {
+        (Some(_), Some(_)) => {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "pass either cookie_file or account",
+            ))
+        }
+        (Some(cookie_file), None) => cookie_file,
+        (None, account) => {
+            AccountStore::open(accounts_dir.unwrap_or_else(AccountStore::default_dir))
+                .and_then(|store| store.resolve(account.as_deref()))
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("{e:#}")))?
+        }
+    };
+    let retry = RetryPolicy {
+        max_attempts: max_attempts.max(1),
+        initial_backoff: seconds("retry_backoff", retry_backoff)?,
+        ..Default::default()
+    };
+    let progress = Progress {
+        interval: seconds("progress_interval", progress_interval)?,
+        on_progress: on_progress.map(|callback| -> OnProgress {
+            Box::new(move |file_name, uploaded, total| {
+                Python::with_gil(|py| {
+                    if let Err(e) = callback.call1(py, (file_name, uploaded, total)) {
+                        e.print(py)
+                    }
+                })
+            })
+        }),
+        on_complete: on_complete.map(|callback| -> OnComplete {
+            Box::new(move |summary| {
+                Python::with_gil(|py| {
+                    if let Err(e) = callback.call1(py, (summary.clone(),)) {
+                        e.print(py)
+                    }
+                })
+            })
+        }),
+    };
+    logging::init();
+    let span = tracing::info_span!("upload", files = video_path.len());
+    Ok(async move {
+        uploader::upload(
+            video_path,
+            cookie_file,
+            line,
+            limit,
+            meta,
+            state_file,
+            retry,
+            progress,
+        )
+        .instrument(span)
+        .await
+        .map_err(upload_error)?;
+        Ok(())
+    })
+}
+
+/// Converts the seconds passed as argument `name`, raising `ValueError` unless they are finite
+/// and not negative.
+fn seconds(name: &str, secs: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(secs).map_err(|_| {
+        pyo3::exceptions::PyValueError::new_err(format!(
+            "{name} must be a finite, non-negative number of seconds, got {secs}"
+        ))
+    })
+}
+
+/// Caps the bytes per second sent by all uploads of the process together, `None` removes
+/// the cap. Can be changed while uploads are running.
+#[pyfunction(bytes_per_sec = "None")]
+fn set_upload_rate_limit(bytes_per_sec: Option<u64>) {
+    UPLOAD_LIMITER.set_rate(bytes_per_sec)
+}
+
+#[pyfunction]
+fn get_upload_rate_limit() -> Option<u64> {
+    UPLOAD_LIMITER.rate()
+}
+
+/// Measures the latency and throughput of every upload line and caches the ranking used when
+/// `upload` is called without a line.
+#[pyfunction]
+fn probe_lines(py: Python<'_>) -> Vec<LineProbe> {
     py.allow_threads(|| {
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()?;
-        // 输出到控制台中
-        let formatting_layer = tracing_subscriber::FmtSubscriber::builder()
-            // will be written to stdout.
-            // builds the subscriber.
-            .finish();
-        let file_appender = tracing_appender::rolling::never("", "upload.log");
-        let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
-        let file_layer = tracing_subscriber::fmt::layer()
-            .with_ansi(false)
-            .with_writer(non_blocking);
-
-        let collector = formatting_layer.with(file_layer);
-
-        tracing::subscriber::with_default(collector, || -> PyResult<()> {
-            match rt.block_on(uploader::upload(
-                video_path,
-                cookie_file,
-                line,
-                limit,
-                title,
-                tid,
-                tag,
-                copyright,
-                source,
-                desc,
-                dynamic,
-                cover,
-                dtime,
-            )) {
-                Ok(_res) => Ok(()),
-                // Ok(_) => {  },
-                Err(err) => {
-                    return Err(pyo3::exceptions::PyRuntimeError::new_err(format!(
-                        "{}, {}",
-                        err.root_cause(),
-                        err
-                    )));
-                }
-            }
-        })
+        let probes = runtime::block_on(probe::probe_lines());
+        LINE_CACHE.put(&probes);
+        probes
     })
 }
 
+/// How long the ranking of `probe_lines` is reused before probing again.
+#[pyfunction]
+fn set_line_cache_ttl(secs: f64) -> PyResult<()> {
+    LINE_CACHE.set_ttl(seconds("secs", secs)?);
+    Ok(())
+}
+
+/// Writes the first H.264 keyframe of the FLV file at `flv` to `output` as an Annex B stream,
+/// which can be decoded into a cover by other tools. Returns its timestamp in milliseconds.
+#[pyfunction]
+fn extract_keyframe(flv: PathBuf, output: PathBuf) -> PyResult<u32> {
+    let keyframe = uploader::cover::first_keyframe(&flv)
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("{e:#}")))?;
+    std::fs::write(output, keyframe.annexb)?;
+    Ok(keyframe.timestamp)
+}
+
+/// Sets up logging for the whole process, replacing the previous configuration. Without a
+/// call, downloads and uploads log at `info` to stdout.
+///
+/// `quiet` keeps stdout clean for piping, only warnings and errors are written, to stderr.
+/// `rotation` is one of `never`, `daily`, `hourly` or `minutely`; rotated files get the date
+/// appended to `path`. `python` forwards records to the `logging` module, under loggers named
+/// like `stream_gears.downloader`.
+#[pyfunction(
+    level = "\"info\"",
+    path = "None",
+    rotation = "\"never\"",
+    json = "false",
+    stdout = "true",
+    quiet = "false",
+    python = "false"
+)]
+fn configure_logging(
+    level: &str,
+    path: Option<PathBuf>,
+    rotation: &str,
+    json: bool,
+    stdout: bool,
+    quiet: bool,
+    python: bool,
+) -> PyResult<()> {
+    let value_error = |e: anyhow::Error| pyo3::exceptions::PyValueError::new_err(e.to_string());
+    let config = logging::LogConfig {
+        level: logging::parse_level(level).map_err(value_error)?,
+        stdout,
+        quiet,
+        path,
+        rotation: logging::parse_rotation(rotation).map_err(value_error)?,
+        json,
+        python,
+    };
+    logging::configure(config).map_err(to_py_err)
+}
+
 /// A Python module implemented in Rust.
 #[pymodule]
-fn stream_gears(_py: Python, m: &PyModule) -> PyResult<()> {
-    // let file_appender = tracing_appender::rolling::daily("", "upload.log");
-    // let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
-    // tracing_subscriber::fmt()
-    //     .with_writer(non_blocking)
-    //     .init();
+fn stream_gears(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(configure_logging, m)?)?;
     m.add_function(wrap_pyfunction!(upload, m)?)?;
+    m.add_function(wrap_pyfunction!(upload_async, m)?)?;
+    m.add_function(wrap_pyfunction!(set_upload_rate_limit, m)?)?;
+    m.add_function(wrap_pyfunction!(probe_lines, m)?)?;
+    m.add_function(wrap_pyfunction!(set_line_cache_ttl, m)?)?;
+    m.add_function(wrap_pyfunction!(get_upload_rate_limit, m)?)?;
     m.add_function(wrap_pyfunction!(download, m)?)?;
+    m.add_function(wrap_pyfunction!(download_async, m)?)?;
+    m.add_function(wrap_pyfunction!(recover, m)?)?;
+    m.add_function(wrap_pyfunction!(extract_keyframe, m)?)?;
     m.add_function(wrap_pyfunction!(login_by_cookies, m)?)?;
     m.add_function(wrap_pyfunction!(send_sms, m)?)?;
     m.add_function(wrap_pyfunction!(login_by_qrcode, m)?)?;
     m.add_function(wrap_pyfunction!(get_qrcode, m)?)?;
-    // m.add_function(wrap_pyfunction(login_by_sms, m)?)?;
+    m.add_function(wrap_pyfunction!(load_credentials, m)?)?;
+    m.add_function(wrap_pyfunction!(refresh_credentials, m)?)?;
+    m.add_function(wrap_pyfunction!(login_by_sms, m)?)?;
+    m.add_function(wrap_pyfunction!(login_by_cookies_async, m)?)?;
+    m.add_function(wrap_pyfunction!(send_sms_async, m)?)?;
+    m.add_function(wrap_pyfunction!(login_by_sms_async, m)?)?;
+    m.add_function(wrap_pyfunction!(login_by_qrcode_async, m)?)?;
+    m.add_function(wrap_pyfunction!(get_qrcode_async, m)?)?;
+    m.add_function(wrap_pyfunction!(load_credentials_async, m)?)?;
+    m.add_function(wrap_pyfunction!(refresh_credentials_async, m)?)?;
+    m.add_class::<SegmentRule>()?;
     m.add_class::<UploadLine>()?;
+    m.add_class::<LineProbe>()?;
+    m.add_class::<Downloader>()?;
+    m.add_class::<Snapshot>()?;
+    m.add_class::<Summary>()?;
+    m.add_class::<StudioMeta>()?;
+    m.add_class::<Submission>()?;
+    m.add_class::<Job>()?;
+    m.add_class::<LiveUpload>()?;
+    m.add_class::<PyUploadQueue>()?;
+    m.add_class::<Credentials>()?;
+    m.add_class::<QrLogin>()?;
+    m.add_class::<Account>()?;
+    m.add_class::<AccountStore>()?;
+    exceptions::register(py, m)?;
+    runtime::register(py)?;
     Ok(())
 }
@@ -0,0 +1,418 @@
+use anyhow::{bail, Result};
+use pyo3::prelude::*;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::layer::{Context, Layered, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{reload, Layer, Registry};
+
+type Filtered = Layered<reload::Layer<LevelFilter, Registry>, Registry>;
+type Outputs = Vec<Box<dyn Layer<Filtered> + Send + Sync>>;
+
+/// Handles to the global subscriber, installed by the first [`configure`] or [`init`].
+struct Handles {
+    level: reload::Handle<LevelFilter, Registry>,
+    outputs: reload::Handle<Outputs, Filtered>,
+}
+
+static INSTALL: Once = Once::new();
+static HANDLES: Mutex<Option<Handles>> = Mutex::new(None);
+/// Flushes the log file when replaced, the writer thread stops with it.
+static FILE_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
+
+/// Where and how log records are written.
+#[derive(Clone, Debug)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    pub stdout: bool,
+    /// Nothing on stdout, only warnings and errors on stderr. Leaves stdout to the output of
+    /// a program, for piping.
+    pub quiet: bool,
+    /// Log file, rotated files get the date appended to its name.
+    pub path: Option<PathBuf>,
+    pub rotation: Rotation,
+    /// One JSON object per line instead of text.
+    pub json: bool,
+    /// Forward records to Python's `logging`, as loggers named after their module path.
+    pub python: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::INFO,
+            stdout: true,
+            quiet: false,
+            path: None,
+            rotation: Rotation::NEVER,
+            json: false,
+            python: false,
+        }
+    }
+}
+
+pub fn parse_level(level: &str) -> Result<LevelFilter> {
+    match level.parse() {
+        Ok(level) => Ok(level),
+        Err(_) => {
+            bail!("unknown log level {level:?}, expected off, error, warn, info, debug or trace")
+        }
+    }
+}
+
+pub fn parse_rotation(rotation: &str) -> Result<Rotation> {
+    Ok(match rotation {
+        "never" => Rotation::NEVER,
+        "daily" => Rotation::DAILY,
+        "hourly" => Rotation::HOURLY,
+        "minutely" => Rotation::MINUTELY,
+        _ => bail!("unknown rotation {rotation:?}, expected never, daily, hourly or minutely"),
+    })
+}
+
+/// Replaces the logging configuration of the process, records of running downloads and
+/// uploads included.
+pub fn configure(config: LogConfig) -> Result<()> {
+    let mut outputs: Outputs = Vec::new();
+    if config.quiet {
+        let stderr = std::io::stderr.with_max_level(Level::WARN);
+        outputs.push(if config.json {
+            Box::new(
+                tracing_subscriber::fmt::layer()
+                    .fmt_fields(JsonFields)
+                    .event_format(JsonFormat)
+                    .with_writer(stderr),
+            )
+        } else {
+            Box::new(tracing_subscriber::fmt::layer().with_writer(stderr))
+        });
+    } else if config.stdout {
+        outputs.push(if config.json {
+            Box::new(
+                tracing_subscriber::fmt::layer()
+                    .fmt_fields(JsonFields)
+                    .event_format(JsonFormat),
+            )
+        } else {
+            Box::new(tracing_subscriber::fmt::layer())
+        });
+    }
+    let mut guard = None;
+    if let Some(path) = &config.path {
+        let (dir, file_name) = split(path)?;
+        std::fs::create_dir_all(&dir)?;
+        let appender = RollingFileAppender::new(config.rotation.clone(), dir, file_name);
+        let (writer, file_guard) = tracing_appender::non_blocking(appender);
+        guard = Some(file_guard);
+        outputs.push(if config.json {
+            Box::new(
+                tracing_subscriber::fmt::layer()
+                    .fmt_fields(JsonFields)
+                    .event_format(JsonFormat)
+                    .with_writer(writer),
+            )
+        } else {
+            Box::new(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(writer),
+            )
+        });
+    }
+    if config.python {
+        outputs.push(Box::new(PythonLayer));
+    }
+    install(config.level, outputs)?;
+    *FILE_GUARD.lock().unwrap_or_else(|e| e.into_inner()) = guard;
+    Ok(())
+}
+
+/// Logs to stdout at `info` unless logging was configured already.
+pub fn init() {
+    INSTALL.call_once(|| {
+        if let Err(e) = install_global(
+            LevelFilter::INFO,
+            vec![Box::new(tracing_subscriber::fmt::layer())],
+        ) {
+            eprintln!("Unable to set up logging: {e}");
+        }
+    });
+}
+
+fn install(level: LevelFilter, outputs: Outputs) -> Result<()> {
+    let mut result = None;
+    let mut outputs = Some(outputs);
+    INSTALL.call_once(|| result = Some(install_global(level, outputs.take().unwrap_or_default())));
+    if let Some(result) = result {
+        return result;
+    }
+    let handles = HANDLES.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(handles) = handles.as_ref() else {
+        bail!("logging is set up by someone else");
+    };
+    handles.level.reload(level)?;
+    handles.outputs.reload(outputs.unwrap_or_default())?;
+    Ok(())
+}
+
+fn install_global(level: LevelFilter, outputs: Outputs) -> Result<()> {
+    let (level, level_handle) = reload::Layer::new(level);
+    let (outputs, outputs_handle) = reload::Layer::new(outputs);
+    tracing::subscriber::set_global_default(Registry::default().with(level).with(outputs))?;
+    *HANDLES.lock().unwrap_or_else(|e| e.into_inner()) = Some(Handles {
+        level: level_handle,
+        outputs: outputs_handle,
+    });
+    Ok(())
+}
+
+fn split(path: &Path) -> Result<(PathBuf, PathBuf)> {
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(file_name)) => {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            Ok((dir.to_path_buf(), file_name.into()))
+        }
+        _ => bail!("invalid log file {}", path.display()),
+    }
+}
+
+/// Collects the fields of an event or span as JSON values.
+#[derive(Default)]
+struct JsonVisitor(Map<String, Value>);
+
+impl Visit for JsonVisitor {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}").into());
+    }
+}
+
+/// Formats span fields as a JSON object, for [`JsonFormat`].
+struct JsonFields;
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut visitor = JsonVisitor::default();
+        fields.record(&mut visitor);
+        write!(writer, "{}", Value::Object(visitor.0))
+    }
+
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &tracing::span::Record<'_>,
+    ) -> fmt::Result {
+        let mut visitor = JsonVisitor(serde_json::from_str(&current.fields).unwrap_or_default());
+        fields.record(&mut visitor);
+        current.fields = Value::Object(visitor.0).to_string();
+        Ok(())
+    }
+}
+
+/// One JSON object per event with its fields and those of the spans it happened in.
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut fields = JsonVisitor::default();
+        event.record(&mut fields);
+        let mut spans = Vec::new();
+        for span in ctx
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+        {
+            let extensions = span.extensions();
+            let mut span_fields: Map<String, Value> = extensions
+                .get::<FormattedFields<N>>()
+                .and_then(|fields| serde_json::from_str(&fields.fields).ok())
+                .unwrap_or_default();
+            span_fields.insert("name".to_string(), span.name().into());
+            spans.push(Value::Object(span_fields));
+        }
+        let metadata = event.metadata();
+        let line = serde_json::json!({
+            "timestamp": chrono::Local::now().to_rfc3339(),
+            "level": metadata.level().as_str(),
+            "target": metadata.target(),
+            "fields": fields.0,
+            "spans": spans,
+        });
+        writeln!(writer, "{line}")
+    }
+}
+
+/// Span fields as text, for [`PythonLayer`].
+struct SpanFields(String);
+
+/// Writes fields as `name=value`, the message first without its name.
+#[derive(Default)]
+struct TextVisitor(String);
+
+impl Visit for TextVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        use std::fmt::Write;
+        let separator = if self.0.is_empty() { "" } else { " " };
+        let _ = if field.name() == "message" {
+            write!(self.0, "{separator}{value:?}")
+        } else {
+            write!(self.0, "{separator}{}={value:?}", field.name())
+        };
+    }
+}
+
+/// Forwards events to the Python `logging` module.
+struct PythonLayer;
+
+impl<S> Layer<S> for PythonLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::span::Id,
+        ctx: Context<'_, S>,
+    ) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = TextVisitor::default();
+            attrs.record(&mut fields);
+            span.extensions_mut().insert(SpanFields(fields.0));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        // Records from runtime threads while the interpreter shuts down are dropped.
+        if crate::runtime::exiting() {
+            return;
+        }
+        let mut message = String::new();
+        for span in ctx
+            .event_scope(event)
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+        {
+            let extensions = span.extensions();
+            let fields = extensions
+                .get::<SpanFields>()
+                .map_or("", |fields| &fields.0);
+            message.push_str(&format!("{}{{{fields}}}: ", span.name()));
+        }
+        let mut fields = TextVisitor::default();
+        event.record(&mut fields);
+        message.push_str(&fields.0);
+        let metadata = event.metadata();
+        let level = match *metadata.level() {
+            Level::ERROR => 40,
+            Level::WARN => 30,
+            Level::INFO => 20,
+            Level::DEBUG => 10,
+            Level::TRACE => 5,
+        };
+        let logger = metadata.target().replace("::", ".");
+        Python::with_gil(|py| {
+            let result = py
+                .import("logging")
+                .and_then(|logging| logging.call_method1("getLogger", (logger,)))
+                .and_then(|logger| logger.call_method1("log", (level, message)));
+            if let Err(e) = result {
+                e.print(py);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn formats_json_with_spans() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = Registry::default().with(
+            tracing_subscriber::fmt::layer()
+                .fmt_fields(JsonFields)
+                .event_format(JsonFormat)
+                .with_writer(move || writer.clone()),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("recording", url = "https://example.com/live.flv");
+            span.in_scope(|| tracing::warn!(size = 42u64, "segment finished"));
+        });
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line: Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["fields"]["message"], "segment finished");
+        assert_eq!(line["fields"]["size"], 42);
+        assert_eq!(line["spans"][0]["name"], "recording");
+        assert_eq!(line["spans"][0]["url"], "https://example.com/live.flv");
+    }
+
+    #[test]
+    fn parses_options() {
+        assert_eq!(parse_level("DEBUG").unwrap(), LevelFilter::DEBUG);
+        assert!(parse_level("loud").is_err());
+        assert_eq!(parse_rotation("hourly").unwrap(), Rotation::HOURLY);
+        assert!(parse_rotation("weekly").is_err());
+    }
+}
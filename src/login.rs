@@ -1,34 +1,900 @@
+use crate::exceptions::to_py_err;
+use crate::runtime::block_on_py;
+use anyhow::{bail, Context, Result};
+use biliup::client;
+use biliup::client::{Client, LoginInfo, OAuthInfo, ResponseData, ResponseValue};
+use fs2::FileExt;
+use pyo3::prelude::*;
+use serde_json::Value;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
 
-use anyhow::{Result};
-use biliup::client::Client;
-use biliup::{client,};
+pub mod store;
 
-pub async fn login_by_cookies()->Result<client::LoginInfo>{
-    let login_info= Client::new().login_by_cookies(std::fs::File::open("cookies.json")?).await?;
+const PASSPORT: &str = "https://passport.bilibili.com";
+
+/// Warn about credentials that cannot be refreshed this long before they expire.
+const EXPIRY_WARNING: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Account stored in a credential file.
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    #[pyo3(get)]
+    pub path: PathBuf,
+    #[pyo3(get)]
+    pub mid: u64,
+    /// Looked up online, `None` if that failed.
+    #[pyo3(get)]
+    pub uname: Option<String>,
+    /// Unix timestamp at which the session cookie expires.
+    #[pyo3(get)]
+    pub expires: Option<u64>,
+    /// App the tokens were issued to, which decides whether they can be refreshed.
+    #[pyo3(get)]
+    pub platform: Option<String>,
+    /// Unix timestamp at which the access token expires, known once checked online.
+    #[pyo3(get)]
+    pub token_expires: Option<u64>,
+    /// Whether checking the credentials renewed them.
+    #[pyo3(get)]
+    pub refreshed: bool,
+}
+
+impl Credentials {
+    pub(crate) fn new(path: &Path, info: &LoginInfo) -> Result<Self> {
+        let token_info = serde_json::to_value(&info.token_info)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            mid: token_info["mid"]
+                .as_u64()
+                .context("credentials without mid")?,
+            uname: None,
+            expires: cookie(info, "SESSDATA").and_then(|cookie| cookie["expires"].as_u64()),
+            platform: info.platform.clone(),
+            token_expires: None,
+            refreshed: false,
+        })
+    }
+}
+
+/// App key and secret of the TV app, which the QR code login uses.
+const BILI_TV: (&str, &str) = ("4409e2ce8ffd12b8", "59b43e04ad6965f34319062b478f83dd");
+
+/// App key and secret of the Android app, which the SMS login uses.
+const ANDROID: (&str, &str) = ("783bbb7264451d82", "2653583c8873dea268ab9386918b1d65");
+
+/// Codes of `oauth2/info` meaning that the token is no longer valid: not logged in, wrong
+/// access key and expired token.
+const INVALID_TOKEN_CODES: [i32; 3] = [-101, -2, -658];
+
+/// App keys the tokens of each platform were issued to.
+fn app_key(platform: &str) -> Option<(&'static str, &'static str)> {
+    match platform {
+        "BiliTV" => Some(BILI_TV),
+        "Android" => Some(ANDROID),
+        _ => None,
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Signed app request parameters, sorted by name as the signature requires.
+fn signed(
+    mut params: Vec<(&str, String)>,
+    (app_key, app_sec): (&str, &str),
+) -> Vec<(String, String)> {
+    params.push(("appkey", app_key.to_string()));
+    params.push(("ts", now().to_string()));
+    params.sort();
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(&params)
+        .finish();
+    let mut params: Vec<_> = params
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+    params.push(("sign".to_string(), Client::sign(&query, app_sec)));
+    params
+}
+
+fn cookie<'a>(info: &'a LoginInfo, name: &str) -> Option<&'a Value> {
+    info.cookie_info["cookies"]
+        .as_array()?
+        .iter()
+        .find(|cookie| cookie["name"] == name)
+}
+
+/// Reads the credential file at `path`.
+pub fn read(path: &Path) -> Result<LoginInfo> {
+    let json = std::fs::read(path).map_err(crate::error::Error::file(path))?;
+    serde_json::from_slice(&json).with_context(|| format!("invalid credentials {}", path.display()))
+}
+
+/// Writes `info` to `path` through a temporary file, so that a crash never leaves a truncated
+/// file behind. Only the owner may read it.
+///
+/// Writers hold a lock on `<path>.lock`, so concurrent refreshes of one file replace it one
+/// after another.
+pub fn save(info: &LoginInfo, path: &Path) -> Result<()> {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    let lock = std::fs::File::create(lock)?;
+    lock.lock_exclusive()?;
+    // Unique to this process, the lock keeps its threads apart.
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    let mut options = std::fs::File::options();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    // The mode only applies to new files, a leftover one keeps its permissions otherwise.
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(&serde_json::to_vec_pretty(info)?)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Reads the account of the credential file at `path`, looking up its name.
+pub async fn load_credentials(path: &Path) -> Result<Credentials> {
+    let info = read(path)?;
+    let mut credentials = Credentials::new(path, &info)?;
+    match uname(&info).await {
+        Ok(uname) => credentials.uname = Some(uname),
+        Err(e) => warn!("Unable to look up the name of {}: {e:#}", credentials.mid),
+    }
+    Ok(credentials)
+}
+
+/// Checks the credential file at `path` and, when the site asks for it and the tokens allow
+/// it, refreshes the session and rewrites the file.
+///
+/// Fails with [`InvalidCredentials`] if the site rejects the token, and with other errors if
+/// it could not be checked or refreshed.
+pub async fn refresh_credentials(path: &Path) -> Result<Credentials> {
+    refresh(PASSPORT, path).await
+}
+
+async fn refresh(passport: &str, path: &Path) -> Result<Credentials> {
+    let mut info = read(path)?;
+    let client = Client::new().client;
+    let token_info = serde_json::to_value(&info.token_info)?;
+    let app_key = info.platform.as_deref().and_then(app_key);
+    let response: ResponseData = client
+        .get(format!("{passport}/x/passport-login/oauth2/info"))
+        .query(&signed(
+            vec![
+                ("access_key", info.token_info.access_token.clone()),
+                ("actionKey", "appkey".to_string()),
+            ],
+            app_key.unwrap_or(ANDROID),
+        ))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let oauth: OAuthInfo = match response.data {
+        ResponseValue::OAuth(oauth) if response.code == 0 => oauth,
+        _ if INVALID_TOKEN_CODES.contains(&response.code) => {
+            return Err(InvalidCredentials {
+                path: path.to_path_buf(),
+                response: response.to_string(),
+            }
+            .into())
+        }
+        _ => bail!("unable to check credentials {}: {response}", path.display()),
+    };
+    let mut token_expires = now() + u64::from(oauth.expires_in);
+    let refreshed = match app_key {
+        Some(app_key) if oauth.refresh => {
+            let response: ResponseData = client
+                .post(format!("{passport}/x/passport-login/oauth2/refresh_token"))
+                .form(&signed(
+                    vec![
+                        ("access_key", info.token_info.access_token.clone()),
+                        ("actionKey", "appkey".to_string()),
+                        (
+                            "refresh_token",
+                            token_info["refresh_token"]
+                                .as_str()
+                                .unwrap_or_default()
+                                .to_string(),
+                        ),
+                    ],
+                    app_key,
+                ))
+                .send()
+                .await?
+                .json()
+                .await?;
+            match response.data {
+                ResponseValue::Login(new_info) if !new_info.cookie_info.is_null() => {
+                    info = LoginInfo {
+                        platform: info.platform,
+                        ..new_info
+                    };
+                }
+                _ => bail!(
+                    "unable to refresh credentials {}: {response}",
+                    path.display()
+                ),
+            }
+            save(&info, path)?;
+            let expires_in = serde_json::to_value(&info.token_info)?["expires_in"].as_u64();
+            token_expires = now() + expires_in.unwrap_or_default();
+            info!("Refreshed credentials {}", path.display());
+            true
+        }
+        _ => false,
+    };
+    let mut credentials = Credentials::new(path, &info)?;
+    credentials.token_expires = Some(token_expires);
+    credentials.refreshed = refreshed;
+    if app_key.is_none() && u64::from(oauth.expires_in) < EXPIRY_WARNING.as_secs() {
+        warn!(
+            expires = token_expires,
+            "Credentials {} cannot be refreshed and expire soon, log in again",
+            path.display()
+        );
+    }
+    Ok(credentials)
+}
+
+async fn uname(info: &LoginInfo) -> Result<String> {
+    let cookies: Vec<String> = info.cookie_info["cookies"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|cookie| {
+            Some(format!(
+                "{}={}",
+                cookie["name"].as_str()?,
+                cookie["value"].as_str()?
+            ))
+        })
+        .collect();
+    let res: Value = Client::new()
+        .client
+        .get("https://api.bilibili.com/x/web-interface/nav")
+        .header(reqwest::header::COOKIE, cookies.join("; "))
+        .send()
+        .await?
+        .json()
+        .await?;
+    res["data"]["uname"]
+        .as_str()
+        .map(str::to_string)
+        .with_context(|| format!("no uname: {res}"))
+}
+
+pub async fn login_by_cookies(path: &Path) -> Result<client::LoginInfo> {
+    let (_, login_info) = crate::uploader::login(path).await?;
     Ok(login_info)
 }
-pub async fn send_sms(country_code: u32, phone: u64) -> Result<serde_json::Value> {
-    let ret = Client::new().send_sms(phone, country_code).await?;
-    Ok(ret)
+/// The site no longer accepts the credential file at `path`.
+#[derive(Debug, thiserror::Error)]
+#[error("credentials {} are no longer valid: {response}", path.display())]
+pub struct InvalidCredentials {
+    pub path: PathBuf,
+    pub response: String,
+}
+
+/// The site refused a login request.
+#[derive(Debug, thiserror::Error)]
+#[error("{message} (code {code})")]
+pub struct LoginFailed {
+    pub code: i64,
+    pub message: String,
+}
+
+/// The site wants a geetest captcha solved before it sends an SMS code. The solution is
+/// passed back to [`send_sms`].
+#[derive(Debug, thiserror::Error)]
+#[error("a captcha has to be solved before the SMS code is sent: {url}")]
+pub struct CaptchaRequired {
+    pub url: String,
+    pub recaptcha_token: String,
+    pub gee_gt: String,
+    pub gee_challenge: String,
+}
+
+impl CaptchaRequired {
+    fn from_url(url: &str) -> Result<Self> {
+        let parsed = url::Url::parse(url)?;
+        let param = |name: &str| {
+            parsed
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+                .with_context(|| format!("no {name} in captcha url {url}"))
+        };
+        Ok(Self {
+            url: url.to_string(),
+            recaptcha_token: param("recaptcha_token")?,
+            gee_gt: param("gee_gt")?,
+            gee_challenge: param("gee_challenge")?,
+        })
+    }
+}
+
+/// Solution of a [`CaptchaRequired`] challenge.
+#[derive(Debug, Default)]
+pub struct Captcha {
+    pub recaptcha_token: String,
+    pub gee_challenge: String,
+    pub gee_validate: String,
+    pub gee_seccode: String,
+}
+
+async fn post_form(url: String, form: &[(String, String)]) -> Result<Value> {
+    let res: Value = Client::new()
+        .client
+        .post(url)
+        .form(form)
+        .send()
+        .await?
+        .json()
+        .await?;
+    match res["code"].as_i64() {
+        Some(0) => Ok(res),
+        code => Err(LoginFailed {
+            code: code.unwrap_or(-1),
+            message: res["message"].as_str().unwrap_or_default().to_string(),
+        }
+        .into()),
+    }
+}
+
+/// Sends a login code to `phone` and returns the request to complete with
+/// [`login_by_sms`]. Fails with [`CaptchaRequired`] when a captcha has to be solved first.
+pub async fn send_sms(
+    country_code: u32,
+    phone: u64,
+    captcha: Option<Captcha>,
+) -> Result<serde_json::Value> {
+    request_sms(PASSPORT, country_code, phone, captcha).await
+}
+
+async fn request_sms(
+    passport: &str,
+    country_code: u32,
+    phone: u64,
+    captcha: Option<Captcha>,
+) -> Result<serde_json::Value> {
+    let mut params = vec![
+        ("actionKey", "appkey".to_string()),
+        ("build", "6510400".to_string()),
+        ("channel", "bili".to_string()),
+        ("cid", country_code.to_string()),
+        ("device", "phone".to_string()),
+        ("mobi_app", "android".to_string()),
+        ("platform", "android".to_string()),
+        ("tel", phone.to_string()),
+    ];
+    if let Some(captcha) = captcha {
+        params.extend([
+            ("recaptcha_token", captcha.recaptcha_token),
+            ("gee_challenge", captcha.gee_challenge),
+            ("gee_validate", captcha.gee_validate),
+            ("gee_seccode", captcha.gee_seccode),
+        ]);
+    }
+    let mut form = signed(params, ANDROID);
+    let res = post_form(format!("{passport}/x/passport-login/sms/send"), &form).await?;
+    let data = &res["data"];
+    match data["captcha_key"].as_str() {
+        Some(captcha_key) if !captcha_key.is_empty() => {
+            form.retain(|(name, _)| name != "sign");
+            form.push(("captcha_key".to_string(), captcha_key.to_string()));
+            Ok(form
+                .into_iter()
+                .map(|(name, value)| (name, Value::from(value)))
+                .collect::<serde_json::Map<_, _>>()
+                .into())
+        }
+        _ => match data["recaptcha_url"].as_str() {
+            Some(url) if !url.is_empty() => Err(CaptchaRequired::from_url(url)?.into()),
+            _ => bail!("unexpected response to sending the SMS code: {res}"),
+        },
+    }
 }
-pub async fn login_by_sms(code: u32, res: serde_json::Value) -> Result<bool> {
-    let info = Client::new().login_by_sms(code, res).await?;
-    let file = std::fs::File::create("cookies.json")?;
-    serde_json::to_writer_pretty(&file, &info)?;
+
+/// Completes an SMS login with the `code` the user received, `res` being what
+/// [`send_sms`] returned, and saves the credentials to `path`.
+pub async fn login_by_sms(code: u32, res: serde_json::Value, path: &Path) -> Result<bool> {
+    sms_login(PASSPORT, code, res, path).await
+}
+
+async fn sms_login(passport: &str, code: u32, res: Value, path: &Path) -> Result<bool> {
+    let params = res
+        .as_object()
+        .context("not a request returned by send_sms")?
+        .iter()
+        .filter(|(name, _)| !matches!(name.as_str(), "sign" | "appkey" | "ts"))
+        .map(|(name, value)| {
+            let value = match value {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            (name.as_str(), value)
+        })
+        .chain([("code", code.to_string())])
+        .collect();
+    let res = post_form(
+        format!("{passport}/x/passport-login/login/sms"),
+        &signed(params, ANDROID),
+    )
+    .await?;
+    let info: LoginInfo = serde_json::from_value(res["data"].clone())
+        .with_context(|| format!("unexpected response to the SMS login: {res}"))?;
+    save(
+        &LoginInfo {
+            platform: Some("Android".to_string()),
+            ..info
+        },
+        path,
+    )?;
     Ok(true)
 }
+
 pub async fn get_qrcode() -> Result<serde_json::Value> {
     let qrcode = Client::new().get_qrcode().await?;
     Ok(qrcode)
 }
-pub async fn login_by_qrcode(res: serde_json::Value) -> Result<bool> {
+pub async fn login_by_qrcode(res: serde_json::Value, path: &Path) -> Result<bool> {
     let info = Client::new().login_by_qrcode(res).await?;
-    let file = std::fs::File::create("cookies.json")?;
-    serde_json::to_writer_pretty(&file, &info)?;
+    save(&info, path)?;
     Ok(true)
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QrStatus {
+    Pending,
+    Scanned,
+    Confirmed,
+    Expired,
+}
+
+impl QrStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            QrStatus::Pending => "pending",
+            QrStatus::Scanned => "scanned",
+            QrStatus::Confirmed => "confirmed",
+            QrStatus::Expired => "expired",
+        }
+    }
+}
+
+/// QR code login that is polled by the caller instead of blocking until it is scanned, so
+/// that a UI can show its progress and give up at any time.
+#[pyclass]
+pub struct QrLogin {
+    passport: String,
+    /// Where the credentials are saved once the login is confirmed.
+    #[pyo3(get)]
+    path: PathBuf,
+    auth_code: Option<String>,
+    url: Option<String>,
+    status: QrStatus,
+}
+
+impl QrLogin {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            passport: PASSPORT.to_string(),
+            path,
+            auth_code: None,
+            url: None,
+            status: QrStatus::Pending,
+        }
+    }
+
+    /// Requests a new QR code and returns the URL it encodes.
+    pub async fn start(&mut self) -> Result<String> {
+        let res: Value = Client::new()
+            .client
+            .post(format!(
+                "{}/x/passport-tv-login/qrcode/auth_code",
+                self.passport
+            ))
+            .form(&signed(vec![("local_id", "0".to_string())], BILI_TV))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let (Some(url), Some(auth_code)) = (
+            res["data"]["url"].as_str(),
+            res["data"]["auth_code"].as_str(),
+        ) else {
+            bail!("unable to get a QR code: {res}");
+        };
+        self.auth_code = Some(auth_code.to_string());
+        self.url = Some(url.to_string());
+        self.status = QrStatus::Pending;
+        Ok(url.to_string())
+    }
+
+    /// Asks once whether the QR code was scanned, saving the credentials when confirmed.
+    pub async fn poll(&mut self) -> Result<QrStatus> {
+        if matches!(self.status, QrStatus::Confirmed | QrStatus::Expired) {
+            return Ok(self.status);
+        }
+        let auth_code = self
+            .auth_code
+            .clone()
+            .context("QR code login not started")?;
+        let res: ResponseData = Client::new()
+            .client
+            .post(format!("{}/x/passport-tv-login/qrcode/poll", self.passport))
+            .form(&signed(
+                vec![("auth_code", auth_code), ("local_id", "0".to_string())],
+                BILI_TV,
+            ))
+            .send()
+            .await?
+            .json()
+            .await?;
+        self.status = match res {
+            ResponseData {
+                code: 0,
+                data: ResponseValue::Login(info),
+                ..
+            } => {
+                save(
+                    &LoginInfo {
+                        platform: Some("BiliTV".to_string()),
+                        ..info
+                    },
+                    &self.path,
+                )?;
+                info!("QR code login saved to {}", self.path.display());
+                QrStatus::Confirmed
+            }
+            ResponseData { code: 86039, .. } => QrStatus::Pending,
+            ResponseData { code: 86090, .. } => QrStatus::Scanned,
+            ResponseData { code: 86038, .. } => QrStatus::Expired,
+            res => bail!("QR code login failed: {res}"),
+        };
+        Ok(self.status)
+    }
+}
+
+/// Modules of the QR code for `url`, `true` for dark ones, without a quiet zone.
+pub fn qr_matrix(url: &str) -> Result<Vec<Vec<bool>>> {
+    let code = qrcode::QrCode::new(url)?;
+    let colors = code.to_colors();
+    Ok(colors
+        .chunks(code.width())
+        .map(|row| {
+            row.iter()
+                .map(|&color| color == qrcode::Color::Dark)
+                .collect()
+        })
+        .collect())
+}
+
+/// Draws `matrix` with half block characters, two rows per line, with a quiet zone.
+pub fn render(matrix: &[Vec<bool>]) -> String {
+    const QUIET: usize = 2;
+    let width = matrix.first().map_or(0, Vec::len) + 2 * QUIET;
+    let dark = |y: usize, x: usize| {
+        x >= QUIET
+            && y >= QUIET
+            && matrix
+                .get(y - QUIET)
+                .and_then(|row| row.get(x - QUIET))
+                .copied()
+                .unwrap_or(false)
+    };
+    let mut text = String::new();
+    for y in (0..matrix.len() + 2 * QUIET).step_by(2) {
+        for x in 0..width {
+            // Light modules are drawn, dark ones are left to the terminal background.
+            text.push(match (dark(y, x), dark(y + 1, x)) {
+                (false, false) => '█',
+                (false, true) => '▀',
+                (true, false) => '▄',
+                (true, true) => ' ',
+            });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// pyo3 0.16 nests the `#[new]` wrapper in a static, outside the scope of an `allow` on the
+// impl itself.
+#[allow(non_local_definitions)]
+const _: () = {
+    #[pymethods]
+    impl QrLogin {
+        #[new]
+        #[args(path = "PathBuf::from(\"cookies.json\")")]
+        fn py_new(path: PathBuf) -> Self {
+            Self::new(path)
+        }
+
+        /// Requests a new QR code, returns the URL and its matrix of modules, `True` for dark.
+        #[pyo3(name = "start")]
+        fn py_start(&mut self, py: Python<'_>) -> PyResult<(String, Vec<Vec<bool>>)> {
+            let url = py.allow_threads(|| block_on_py(self.start()))?;
+            let matrix = qr_matrix(&url).map_err(to_py_err)?;
+            Ok((url, matrix))
+        }
+
+        /// Checks once and returns "pending", "scanned", "confirmed" or "expired".
+        #[pyo3(name = "poll")]
+        fn py_poll(&mut self, py: Python<'_>) -> PyResult<&'static str> {
+            py.allow_threads(|| block_on_py(self.poll()))
+                .map(QrStatus::as_str)
+        }
+
+        #[getter]
+        fn status(&self) -> &'static str {
+            self.status.as_str()
+        }
+
+        #[getter]
+        fn url(&self) -> Option<String> {
+            self.url.clone()
+        }
+
+        /// The QR code as text for a terminal.
+        #[pyo3(name = "render")]
+        fn py_render(&self) -> PyResult<String> {
+            let url = self.url.as_deref().ok_or_else(|| {
+                pyo3::exceptions::PyRuntimeError::new_err("QR code login not started")
+            })?;
+            Ok(render(&qr_matrix(url).map_err(to_py_err)?))
+        }
+    }
+};
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        qr_matrix, read, refresh, render, request_sms, save, sms_login, Captcha, CaptchaRequired,
+        Credentials, InvalidCredentials, LoginFailed, QrLogin, QrStatus, BILI_TV,
+    };
+    use biliup::client::LoginInfo;
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
+    fn login_info(access_token: &str, sessdata_expires: u64) -> serde_json::Value {
+        json!({
+            "cookie_info": {"cookies": [
+                {"name": "bili_jct", "value": "jct", "expires": 1700000000},
+                {"name": "SESSDATA", "value": "sess", "expires": sessdata_expires},
+            ]},
+            "sso": [],
+            "token_info": {
+                "access_token": access_token,
+                "expires_in": 15552000,
+                "mid": 42,
+                "refresh_token": "refresh",
+            },
+            "platform": "BiliTV",
+        })
+    }
 
+    #[test]
+    fn saves_credentials() -> anyhow::Result<()> {
+        let info: LoginInfo = serde_json::from_value(login_info("access", 1700000001))?;
+        let path = std::env::temp_dir().join(format!("credentials-{}.json", std::process::id()));
+        // A leftover temporary file does not keep its permissions.
+        let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+        std::fs::write(&tmp, "")?;
+        #[cfg(unix)]
+        std::fs::set_permissions(&tmp, std::os::unix::fs::PermissionsExt::from_mode(0o644))?;
+        std::thread::scope(|scope| {
+            let saves: Vec<_> = (0..4).map(|_| scope.spawn(|| save(&info, &path))).collect();
+            saves.into_iter().try_for_each(|save| save.join().unwrap())
+        })?;
+        assert!(!tmp.exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                std::fs::metadata(&path)?.permissions().mode() & 0o777,
+                0o600
+            );
+        }
+        let credentials = Credentials::new(&path, &read(&path)?)?;
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(path.with_extension("json.lock"))?;
+        assert_eq!(credentials.mid, 42);
+        assert_eq!(credentials.expires, Some(1700000001));
+        assert_eq!(credentials.platform.as_deref(), Some("BiliTV"));
+        Ok(())
+    }
 
+    #[tokio::test]
+    async fn refreshes_credentials() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/x/passport-login/oauth2/info"))
+            .and(query_param("access_key", "old"))
+            .and(query_param("appkey", BILI_TV.0))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": 0, "message": "0", "ttl": 1,
+                "data": {"mid": 42, "access_token": "old", "expires_in": 3600, "refresh": true},
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/x/passport-login/oauth2/refresh_token"))
+            .and(body_string_contains("refresh_token=refresh"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": 0, "message": "0", "ttl": 1,
+                "data": login_info("new", 1800000000),
+            })))
+            .mount(&server)
+            .await;
+        let file = std::env::temp_dir().join(format!("refresh-{}.json", std::process::id()));
+        std::fs::write(&file, login_info("old", 1700000001).to_string())?;
 
+        let credentials = refresh(&server.uri(), &file).await?;
+        let info = read(&file)?;
+        assert!(credentials.refreshed);
+        assert_eq!(credentials.expires, Some(1800000000));
+        assert_eq!(info.token_info.access_token, "new");
+        assert_eq!(info.platform.as_deref(), Some("BiliTV"));
+
+        // The new token is revoked, the file is left alone.
+        Mock::given(method("GET"))
+            .and(path("/x/passport-login/oauth2/info"))
+            .and(query_param("access_key", "new"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": -101, "message": "账号未登录", "ttl": 1, "data": {},
+            })))
+            .mount(&server)
+            .await;
+        let e = refresh(&server.uri(), &file).await.unwrap_err();
+        assert!(e.is::<InvalidCredentials>());
+        assert_eq!(read(&file)?.token_info.access_token, "new");
+
+        // Other failures do not mean that the token is invalid.
+        std::fs::write(&file, login_info("busy", 1800000000).to_string())?;
+        Mock::given(method("GET"))
+            .and(path("/x/passport-login/oauth2/info"))
+            .and(query_param("access_key", "busy"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": -412, "message": "请求被拦截", "ttl": 1, "data": {},
+            })))
+            .mount(&server)
+            .await;
+        let e = refresh(&server.uri(), &file).await.unwrap_err();
+        assert!(!e.is::<InvalidCredentials>());
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn polls_qrcode() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/x/passport-tv-login/qrcode/auth_code"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": 0, "message": "0", "ttl": 1,
+                "data": {"url": "https://passport.bilibili.com/x/passport-tv-login/h5/qrcode/auth?auth_code=abc", "auth_code": "abc"},
+            })))
+            .mount(&server)
+            .await;
+        for code in [86039, 86090] {
+            Mock::given(method("POST"))
+                .and(path("/x/passport-tv-login/qrcode/poll"))
+                .and(body_string_contains("auth_code=abc"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "code": code, "message": "", "ttl": 1, "data": null,
+                })))
+                .up_to_n_times(1)
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("POST"))
+            .and(path("/x/passport-tv-login/qrcode/poll"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": 0, "message": "0", "ttl": 1, "data": login_info("access", 1700000001),
+            })))
+            .mount(&server)
+            .await;
+        let file = std::env::temp_dir().join(format!("qrcode-{}.json", std::process::id()));
+        let mut login = QrLogin::new(file.clone());
+        login.passport = server.uri();
+        assert!(login.poll().await.is_err());
+
+        let url = login.start().await?;
+        assert!(url.ends_with("auth_code=abc"));
+        assert_eq!(login.poll().await?, QrStatus::Pending);
+        assert_eq!(login.poll().await?, QrStatus::Scanned);
+        assert_eq!(login.poll().await?, QrStatus::Confirmed);
+        assert_eq!(read(&file)?.platform.as_deref(), Some("BiliTV"));
+        std::fs::remove_file(&file)?;
+
+        let matrix = qr_matrix(&url)?;
+        assert!(matrix.iter().all(|row| row.len() == matrix.len()));
+        assert_eq!(render(&matrix).lines().count(), (matrix.len() + 5) / 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sms_login_with_captcha() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/x/passport-login/sms/send"))
+            .and(body_string_contains("gee_validate=validate"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": 0, "message": "0", "ttl": 1, "data": {"captcha_key": "key", "recaptcha_url": ""},
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/x/passport-login/sms/send"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": 0, "message": "0", "ttl": 1, "data": {
+                    "captcha_key": "",
+                    "recaptcha_url": "https://www.bilibili.com/h5/project-msg-auth/verify?ct=geo&recaptcha_token=token&gee_gt=gt&gee_challenge=challenge",
+                },
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/x/passport-login/login/sms"))
+            .and(body_string_contains("code=123456"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": 0, "message": "0", "ttl": 1, "data": login_info("access", 1700000001),
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/x/passport-login/login/sms"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": 1006, "message": "请输入正确的短信验证码", "ttl": 1, "data": null,
+            })))
+            .mount(&server)
+            .await;
+
+        let err = request_sms(&server.uri(), 86, 13800000000, None)
+            .await
+            .unwrap_err();
+        let challenge = err.downcast_ref::<CaptchaRequired>().unwrap();
+        assert_eq!(
+            (
+                challenge.recaptcha_token.as_str(),
+                challenge.gee_gt.as_str()
+            ),
+            ("token", "gt")
+        );
+        let captcha = Captcha {
+            recaptcha_token: challenge.recaptcha_token.clone(),
+            gee_challenge: challenge.gee_challenge.clone(),
+            gee_validate: "validate".to_string(),
+            gee_seccode: "validate|jordan".to_string(),
+        };
+        let res = request_sms(&server.uri(), 86, 13800000000, Some(captcha)).await?;
+        assert_eq!(res["captcha_key"], "key");
+
+        let file = std::env::temp_dir().join(format!("sms-{}.json", std::process::id()));
+        let err = sms_login(&server.uri(), 1, res.clone(), &file)
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<LoginFailed>().unwrap().code, 1006);
+        assert!(!file.exists());
+        sms_login(&server.uri(), 123456, res, &file).await?;
+        assert_eq!(read(&file)?.platform.as_deref(), Some("Android"));
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,466 @@
+use crate::exceptions::to_py_err;
+use crate::login::{self, Credentials, QrLogin};
+use crate::runtime::block_on_py;
+use anyhow::{bail, Context, Result};
+use fs2::FileExt;
+use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+const INDEX: &str = "accounts.json";
+
+/// Held while the index is replaced, by every process using the store.
+const LOCK: &str = "accounts.lock";
+
+/// An account of an [`AccountStore`], its credentials are `<name>.json` in the store.
+#[pyclass]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Account {
+    #[pyo3(get)]
+    pub name: String,
+    #[pyo3(get)]
+    pub mid: u64,
+    #[pyo3(get)]
+    pub uname: Option<String>,
+    #[pyo3(get)]
+    pub platform: Option<String>,
+    /// Unix timestamp at which the session cookie expires.
+    #[pyo3(get)]
+    pub expires: Option<u64>,
+    /// Unix timestamp at which the account was added.
+    #[pyo3(get)]
+    pub added: u64,
+    #[serde(skip)]
+    #[pyo3(get)]
+    pub path: PathBuf,
+    #[serde(skip)]
+    #[pyo3(get)]
+    pub default: bool,
+}
+
+#[pymethods]
+impl Account {
+    fn __repr__(&self) -> String {
+        format!(
+            "Account(name={:?}, mid={}, uname={:?}, default={})",
+            self.name, self.mid, self.uname, self.default
+        )
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    default: Option<String>,
+    accounts: BTreeMap<String, Account>,
+}
+
+impl Index {
+    /// Records `name`, keeping the time it was first added.
+    fn insert(&mut self, name: &str, credentials: &Credentials, added: u64) -> Account {
+        let added = self
+            .accounts
+            .get(name)
+            .map_or(added, |account| account.added);
+        self.accounts.insert(
+            name.to_string(),
+            Account {
+                name: name.to_string(),
+                mid: credentials.mid,
+                uname: credentials.uname.clone(),
+                platform: credentials.platform.clone(),
+                expires: credentials.expires,
+                added,
+                path: credentials.path.clone(),
+                default: false,
+            },
+        );
+        self.account(name).expect("just inserted")
+    }
+
+    fn account(&self, name: &str) -> Option<Account> {
+        let mut account = self.accounts.get(name)?.clone();
+        account.default = self.default.as_deref() == Some(name);
+        Some(account)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// A directory of named accounts, one credential file each, and `accounts.json` with their
+/// metadata and the default account.
+#[pyclass]
+pub struct AccountStore {
+    dir: PathBuf,
+}
+
+impl AccountStore {
+    /// `$STREAM_GEARS_ACCOUNTS`, or `.stream-gears/accounts` in the home directory.
+    pub fn default_dir() -> PathBuf {
+        match std::env::var_os("STREAM_GEARS_ACCOUNTS") {
+            Some(dir) => dir.into(),
+            None => std::env::var_os("HOME")
+                .map(PathBuf::from)
+                .unwrap_or_default()
+                .join(".stream-gears")
+                .join("accounts"),
+        }
+    }
+
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("unable to create {}", dir.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+        }
+        Ok(Self { dir })
+    }
+
+    /// Credential file of the account `name`, which may not exist yet.
+    pub fn path(&self, name: &str) -> Result<PathBuf> {
+        let valid = !name.is_empty()
+            && name != "accounts"
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            bail!("invalid account name {name:?}");
+        }
+        Ok(self.dir.join(format!("{name}.json")))
+    }
+
+    /// Copies the credential file `cookie_file` into the store as `name`, after logging in
+    /// with it.
+    pub async fn import(&self, name: &str, cookie_file: &Path) -> Result<Account> {
+        let path = self.path(name)?;
+        login::save(&login::read(cookie_file)?, &path)?;
+        if let Err(e) = crate::uploader::login(&path).await {
+            std::fs::remove_file(&path)?;
+            return Err(e.context(format!("unable to log in with {}", cookie_file.display())));
+        }
+        self.register(name).await
+    }
+
+    /// Starts adding `name` by QR code, the account is listed once the login is confirmed.
+    pub fn qr_login(&self, name: &str) -> Result<QrLogin> {
+        Ok(QrLogin::new(self.path(name)?))
+    }
+
+    /// Adds `name` by completing an SMS login, `res` being what `send_sms` returned.
+    pub async fn login_by_sms(
+        &self,
+        name: &str,
+        code: u32,
+        res: serde_json::Value,
+    ) -> Result<Account> {
+        login::login_by_sms(code, res, &self.path(name)?).await?;
+        self.register(name).await
+    }
+
+    /// Records the metadata of the credential file of `name`, looking up its user name.
+    pub async fn register(&self, name: &str) -> Result<Account> {
+        let credentials = login::load_credentials(&self.path(name)?).await?;
+        self.update(|index| Ok(index.insert(name, &credentials, now())))
+    }
+
+    /// All accounts by name, including credential files that were written by a QR code login
+    /// but not recorded yet.
+    pub fn list(&self) -> Result<Vec<Account>> {
+        let index = self.load()?;
+        Ok(index
+            .accounts
+            .keys()
+            .filter_map(|name| index.account(name))
+            .collect())
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<Account>> {
+        Ok(self.load()?.account(name))
+    }
+
+    pub fn remove(&self, name: &str) -> Result<()> {
+        let path = self.path(name)?;
+        self.update(|index| {
+            if index.accounts.remove(name).is_none() && !path.exists() {
+                bail!("no account {name:?}");
+            }
+            if index.default.as_deref() == Some(name) {
+                index.default = None;
+            }
+            Ok(())
+        })?;
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn set_default(&self, name: &str) -> Result<()> {
+        if self.get(name)?.is_none() {
+            bail!("no account {name:?}");
+        }
+        self.update(|index| {
+            index.default = Some(name.to_string());
+            Ok(())
+        })
+    }
+
+    pub fn default(&self) -> Result<Option<Account>> {
+        let index = self.load()?;
+        Ok(index
+            .default
+            .as_deref()
+            .and_then(|name| index.account(name)))
+    }
+
+    /// Credential file of the account `name`, or of the default account.
+    pub fn resolve(&self, name: Option<&str>) -> Result<PathBuf> {
+        let account = match name {
+            Some(name) => self
+                .get(name)?
+                .with_context(|| format!("no account {name:?}"))?,
+            None => self
+                .default()?
+                .with_context(|| format!("no accounts in {}", self.dir.display()))?,
+        };
+        Ok(account.path)
+    }
+
+    /// Reads the index as it is on disk: credential files removed by hand are gone, those
+    /// not recorded yet are added as of when they were written, and without a default the
+    /// oldest account is the default. Only [`Self::update`] writes it back.
+    fn load(&self) -> Result<Index> {
+        let mut index: Index = match std::fs::read(self.dir.join(INDEX)) {
+            Ok(json) => serde_json::from_slice(&json)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Index::default(),
+            Err(e) => return Err(e.into()),
+        };
+        index.accounts.retain(|name, account| {
+            account.path = self.dir.join(format!("{name}.json"));
+            account.path.exists()
+        });
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) if name != INDEX => name.strip_suffix(".json"),
+                _ => None,
+            };
+            let Some(name) = name.filter(|name| !index.accounts.contains_key(*name)) else {
+                continue;
+            };
+            let credentials = self
+                .path(name)
+                .and_then(|path| Credentials::new(&path, &login::read(&path)?));
+            match credentials {
+                Ok(credentials) => {
+                    let added = entry
+                        .metadata()?
+                        .modified()?
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs();
+                    index.insert(name, &credentials, added);
+                }
+                Err(e) => warn!("Skipping {name} in {}: {e:#}", self.dir.display()),
+            }
+        }
+        if !index
+            .default
+            .as_ref()
+            .is_some_and(|name| index.accounts.contains_key(name))
+        {
+            index.default = index
+                .accounts
+                .values()
+                .min_by_key(|account| account.added)
+                .map(|account| account.name.clone());
+        }
+        Ok(index)
+    }
+
+    /// Runs `f` on the index and saves it, holding `accounts.lock` so that concurrent updates,
+    /// from this process or others, are not lost.
+    fn update<T>(&self, f: impl FnOnce(&mut Index) -> Result<T>) -> Result<T> {
+        let lock = std::fs::File::create(self.dir.join(LOCK))?;
+        lock.lock_exclusive()?;
+        let mut index = self.load()?;
+        let result = f(&mut index)?;
+        // Unique to this process, the lock keeps its threads apart.
+        let tmp = self.dir.join(format!("{INDEX}.{}.tmp", std::process::id()));
+        let mut options = std::fs::File::options();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&tmp)?;
+        #[cfg(unix)]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+        file.write_all(&serde_json::to_vec_pretty(&index)?)?;
+        std::fs::rename(&tmp, self.dir.join(INDEX))?;
+        Ok(result)
+    }
+}
+
+// pyo3 0.16 nests the `#[new]` wrapper in a static, outside the scope of an `allow` on the
+// impl itself.
+#[allow(non_local_definitions)]
+const _: () = {
+    #[pymethods]
+    impl AccountStore {
+        /// Opens the store in `dir`, see `default_dir` for where it is by default.
+        #[new]
+        #[args(dir = "None")]
+        fn py_new(dir: Option<PathBuf>) -> PyResult<Self> {
+            Self::open(dir.unwrap_or_else(Self::default_dir)).map_err(to_py_err)
+        }
+
+        #[getter]
+        fn dir(&self) -> PathBuf {
+            self.dir.clone()
+        }
+
+        #[pyo3(name = "path")]
+        fn py_path(&self, name: &str) -> PyResult<PathBuf> {
+            self.path(name).map_err(to_py_err)
+        }
+
+        /// Adds `name` from the credential file `cookie_file`, which is copied into the store.
+        fn import_cookies(
+            &self,
+            py: Python<'_>,
+            name: &str,
+            cookie_file: PathBuf,
+        ) -> PyResult<Account> {
+            py.allow_threads(|| block_on_py(self.import(name, &cookie_file)))
+        }
+
+        /// Returns a `QrLogin` that saves to the account `name` once confirmed.
+        #[pyo3(name = "qr_login")]
+        fn py_qr_login(&self, name: &str) -> PyResult<QrLogin> {
+            self.qr_login(name).map_err(to_py_err)
+        }
+
+        /// Adds `name` with the SMS `code` and the request `send_sms` returned.
+        #[pyo3(name = "login_by_sms")]
+        fn py_login_by_sms(
+            &self,
+            py: Python<'_>,
+            name: &str,
+            code: u32,
+            ret: &str,
+        ) -> PyResult<Account> {
+            let ret = serde_json::from_str(ret)
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+            py.allow_threads(|| block_on_py(self.login_by_sms(name, code, ret)))
+        }
+
+        /// Looks up the user name of `name` again.
+        #[pyo3(name = "register")]
+        fn py_register(&self, py: Python<'_>, name: &str) -> PyResult<Account> {
+            py.allow_threads(|| block_on_py(self.register(name)))
+        }
+
+        #[pyo3(name = "list")]
+        fn py_list(&self) -> PyResult<Vec<Account>> {
+            self.list().map_err(to_py_err)
+        }
+
+        #[pyo3(name = "get")]
+        fn py_get(&self, name: &str) -> PyResult<Option<Account>> {
+            self.get(name).map_err(to_py_err)
+        }
+
+        #[pyo3(name = "remove")]
+        fn py_remove(&self, name: &str) -> PyResult<()> {
+            self.remove(name).map_err(to_py_err)
+        }
+
+        /// Returns the default account, after making `name` the default if given.
+        #[pyo3(name = "default")]
+        #[args(name = "None")]
+        fn py_default(&self, name: Option<&str>) -> PyResult<Option<Account>> {
+            if let Some(name) = name {
+                self.set_default(name).map_err(to_py_err)?;
+            }
+            self.default().map_err(to_py_err)
+        }
+    }
+};
+
+#[cfg(test)]
+mod tests {
+    use super::AccountStore;
+    use std::time::Duration;
+
+    #[test]
+    fn manages_accounts() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("accounts-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = AccountStore::open(&dir)?;
+        assert!(store.path("../escape").is_err());
+        assert!(store.resolve(None).is_err());
+        for (name, mid) in [("main", 1), ("alt", 2)] {
+            let info = serde_json::json!({
+                "cookie_info": {"cookies": []},
+                "sso": [],
+                "token_info": {"access_token": "a", "expires_in": 1, "mid": mid, "refresh_token": "r"},
+                "platform": "BiliTV",
+            });
+            // As left behind by a QR code login, a minute apart.
+            std::fs::write(store.path(name)?, info.to_string())?;
+            std::fs::File::options()
+                .write(true)
+                .open(store.path(name)?)?
+                .set_modified(std::time::SystemTime::now() + Duration::from_secs(60 * mid))?;
+            if name == "main" {
+                assert_eq!(store.default()?.unwrap().mid, 1);
+            }
+        }
+        let accounts = store.list()?;
+        assert_eq!(
+            accounts
+                .iter()
+                .map(|account| (account.name.as_str(), account.default))
+                .collect::<Vec<_>>(),
+            [("alt", false), ("main", true)]
+        );
+        assert_eq!(store.resolve(Some("alt"))?, dir.join("alt.json"));
+
+        store.set_default("alt")?;
+        assert_eq!(store.resolve(None)?, dir.join("alt.json"));
+        store.remove("alt")?;
+        assert!(!dir.join("alt.json").exists());
+        assert_eq!(store.default()?.unwrap().name, "main");
+        // Reading never writes the index, only changes do.
+        let index = std::fs::read(dir.join("accounts.json"))?;
+        store.list()?;
+        store.resolve(None)?;
+        assert_eq!(std::fs::read(dir.join("accounts.json"))?, index);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                std::fs::metadata(dir.join("accounts.json"))?
+                    .permissions()
+                    .mode()
+                    & 0o777,
+                0o600
+            );
+        }
+        assert!(store.set_default("alt").is_err());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::env;
 use std::io::{BufReader, BufWriter, ErrorKind, Read};
+use std::path::Path;
 use std::time::Duration;
 use stream_gears::downloader::httpflv::{download, map_parse_err, Connection};
+use stream_gears::downloader::recovery;
+use stream_gears::downloader::stats::Stats;
 use stream_gears::downloader::util::Segment;
 use stream_gears::error::Error;
 use stream_gears::flv_parser::{
@@ -10,11 +13,32 @@ use stream_gears::flv_parser::{
     CodecId, SoundFormat, TagData,
 };
 use stream_gears::flv_writer::{self, FlvTag, TagDataHeader};
+use stream_gears::uploader::meta::StudioMeta;
+use stream_gears::uploader::queue::{job_retry_policy, UploadQueue};
+use tracing_subscriber::fmt::writer::MakeWriterExt;
 
 fn main() -> Result<(), Error> {
-    tracing_subscriber::fmt::init();
-
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    // `--quiet` leaves stdout to the output of the commands, warnings and errors go to stderr.
+    let quiet = args.iter().any(|arg| arg == "--quiet");
+    args.retain(|arg| arg != "--quiet");
+    if quiet {
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr.with_max_level(tracing::Level::WARN))
+            .init();
+    } else {
+        tracing_subscriber::fmt::init();
+    }
+    if args.get(1).map(String::as_str) == Some("recover") {
+        return recover(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("upload-queue") {
+        if let Err(e) = upload_queue(&args[2..]) {
+            eprintln!("{e:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     let file_name = &args[1];
     let flv_file = std::fs::File::open(file_name)?;
     let buf_reader = BufReader::new(flv_file);
@@ -24,12 +48,98 @@ fn main() -> Result<(), Error> {
         connection,
         &(file_name.to_owned() + "new%H_%M_%S%.f"),
         Segment::Time(Duration::from_secs(60 * 60 * 24), Default::default()),
-    );
+        &Stats::new(),
+        Default::default(),
+    )?;
     // Ok(result)
     // generate_json()?;
     Ok(())
 }
 
+/// `stream-gears recover <dir> [--no-metadata]`
+fn recover(args: &[String]) -> Result<(), Error> {
+    let rewrite_metadata = !args.iter().any(|arg| arg == "--no-metadata");
+    let dir = args
+        .iter()
+        .find(|arg| !arg.starts_with("--"))
+        .map_or(".", String::as_str);
+    for recovered in recovery::recover_dir(Path::new(dir), rewrite_metadata)? {
+        println!(
+            "{} ({} bytes truncated)",
+            recovered.path.display(),
+            recovered.truncated
+        );
+    }
+    Ok(())
+}
+
+const UPLOAD_QUEUE_USAGE: &str = "usage:
+    stream-gears upload-queue <dir> run [--workers N] [--max-attempts N]
+    stream-gears upload-queue <dir> add <cookie_file> <meta.json> <video>...
+    stream-gears upload-queue <dir> status [id]
+    stream-gears upload-queue <dir> retry <id>
+    stream-gears upload-queue <dir> remove <id>";
+
+/// `stream-gears upload-queue <dir> <command> ...`, see [`UPLOAD_QUEUE_USAGE`].
+///
+/// `meta.json` holds the fields of `StudioMeta`, e.g. `{"title": "...", "tag": "a,b"}`.
+fn upload_queue(args: &[String]) -> anyhow::Result<()> {
+    let (dir, command, args) = match args {
+        [dir, command, args @ ..] => (dir, command.as_str(), args),
+        _ => anyhow::bail!(UPLOAD_QUEUE_USAGE),
+    };
+    let queue = UploadQueue::open(dir)?;
+    match (command, args) {
+        ("run", _) => {
+            let option = |name: &str, default: usize| -> anyhow::Result<usize> {
+                match args.iter().position(|arg| arg == name) {
+                    Some(i) => Ok(args.get(i + 1).map_or("", String::as_str).parse()?),
+                    None => Ok(default),
+                }
+            };
+            let workers = option("--workers", 1)?;
+            let retry = job_retry_policy(option("--max-attempts", 3)? as u32);
+            let _lock = queue.acquire()?;
+            tokio::runtime::Runtime::new()?.block_on(async {
+                tokio::select! {
+                    _ = queue.run(workers, &retry) => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            });
+            queue.requeue_running()?;
+        }
+        ("add", [cookie_file, meta, video_path @ ..]) if !video_path.is_empty() => {
+            let meta: StudioMeta = serde_json::from_slice(&std::fs::read(meta)?)?;
+            let video_path = video_path.iter().map(Into::into).collect();
+            let job = queue.enqueue(video_path, Path::new(cookie_file), meta, None, 3)?;
+            println!("{}", job.id);
+        }
+        ("status", [id]) => {
+            let job = queue
+                .get(id)?
+                .ok_or_else(|| anyhow::anyhow!("no job {id}"))?;
+            println!("{}", serde_json::to_string_pretty(&job)?);
+        }
+        ("status", []) => {
+            for job in queue.jobs()? {
+                println!(
+                    "{}\t{:?}\t{}\t{}\t{}",
+                    job.id,
+                    job.status,
+                    job.attempts,
+                    job.meta.title,
+                    job.error.unwrap_or_default()
+                );
+            }
+        }
+        ("retry", [id]) => queue.retry(id)?,
+        ("remove", [id]) => queue.remove(id)?,
+        _ => anyhow::bail!(UPLOAD_QUEUE_USAGE),
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
 fn generate_json() -> Result<(), Error> {
     let args: Vec<String> = env::args().collect();
     let file_name = &args[1];
@@ -160,7 +270,7 @@ impl<T: Read> Reader<T> {
         loop {
             if chunk_size <= self.buffer.len() {
                 let bytes = Bytes::copy_from_slice(&self.buffer[..chunk_size]);
-                self.buffer.advance(chunk_size as usize);
+                self.buffer.advance(chunk_size);
                 return Ok(bytes);
             }
             // BytesMut::with_capacity(0).deref_mut()
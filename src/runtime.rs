@@ -0,0 +1,114 @@
+//! The tokio runtime shared by all calls from Python, and the bridge from its futures to
+//! asyncio.
+
+use crate::exceptions::to_py_err;
+use pyo3::prelude::*;
+use pyo3::types::{PyCFunction, PyDict, PyTuple};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use tokio::runtime::Runtime;
+use tracing::debug;
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+/// Set by `atexit`, the GIL can not be taken from other threads while the interpreter exits.
+static EXITING: AtomicBool = AtomicBool::new(false);
+
+pub fn runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("stream-gears")
+            .build()
+            .expect("Unable to start the tokio runtime.")
+    })
+}
+
+/// Whether the interpreter is exiting, the GIL must not be taken from other threads then.
+pub fn exiting() -> bool {
+    EXITING.load(Ordering::Relaxed)
+}
+
+/// Runs `future` to completion on the shared runtime. Must not be called from one of its
+/// threads, e.g. in a progress callback.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    runtime().block_on(future)
+}
+
+/// [`block_on`] for fallible futures, raising their error as the matching Python exception.
+pub fn block_on_py<T>(future: impl Future<Output = anyhow::Result<T>>) -> PyResult<T> {
+    block_on(future).map_err(to_py_err)
+}
+
+/// Spawns `future` on the shared runtime and returns an asyncio future of its result, bound
+/// to the running event loop. Cancelling the asyncio future drops `future`.
+pub fn future_into_py<F, T>(py: Python<'_>, future: F) -> PyResult<&PyAny>
+where
+    F: Future<Output = PyResult<T>> + Send + 'static,
+    T: IntoPy<PyObject> + Send + 'static,
+{
+    let event_loop: PyObject = py
+        .import("asyncio")?
+        .call_method0("get_running_loop")?
+        .into();
+    let py_future: PyObject = event_loop.call_method0(py, "create_future")?;
+    let result_future = py_future.clone_ref(py);
+    let task = runtime().spawn(async move {
+        let result = future.await;
+        if exiting() {
+            return;
+        }
+        Python::with_gil(|py| {
+            let (ok, value) = match result {
+                Ok(value) => (true, value.into_py(py)),
+                Err(e) => (false, e.into_value(py).into_py(py)),
+            };
+            let resolved = PyCFunction::new_closure(resolve, py).and_then(|resolve| {
+                event_loop.call_method1(
+                    py,
+                    "call_soon_threadsafe",
+                    (resolve, result_future, ok, value),
+                )
+            });
+            // The event loop may be closed by now, nobody is waiting then.
+            if let Err(e) = resolved {
+                debug!("Unable to hand over a result to asyncio: {e}");
+            }
+        })
+    });
+    let task = Mutex::new(Some(task));
+    let cancel = PyCFunction::new_closure(
+        move |args: &PyTuple, _kwargs: Option<&PyDict>| -> PyResult<()> {
+            let future = args.get_item(0)?;
+            if future.call_method0("cancelled")?.is_true()? {
+                if let Some(task) = task.lock().unwrap().take() {
+                    task.abort();
+                }
+            }
+            Ok(())
+        },
+        py,
+    )?;
+    py_future.call_method1(py, "add_done_callback", (cancel,))?;
+    Ok(py_future.into_ref(py))
+}
+
+pub fn register(py: Python<'_>) -> PyResult<()> {
+    let exiting = PyCFunction::new_closure(
+        |_args: &PyTuple, _kwargs: Option<&PyDict>| EXITING.store(true, Ordering::Relaxed),
+        py,
+    )?;
+    py.import("atexit")?.call_method1("register", (exiting,))?;
+    Ok(())
+}
+
+/// Sets the result of an asyncio future on its event loop, unless it was cancelled.
+fn resolve(args: &PyTuple, _kwargs: Option<&PyDict>) -> PyResult<()> {
+    let (future, ok, value): (&PyAny, bool, PyObject) = args.extract()?;
+    if future.call_method0("done")?.is_true()? {
+        return Ok(());
+    }
+    let method = if ok { "set_result" } else { "set_exception" };
+    future.call_method1(method, (value,))?;
+    Ok(())
+}
@@ -1,17 +1,35 @@
+pub mod cos;
+pub mod cover;
+pub mod edit;
+pub mod kodo;
+pub mod limiter;
+pub mod live;
+pub mod meta;
+pub mod probe;
+pub mod progress;
+pub mod queue;
+pub mod retry;
+pub mod session;
+pub mod upos;
+
 use anyhow::{Context, Result};
-use biliup::client::Client;
-use biliup::line::{self, Probe};
-use biliup::video::{BiliBili, Studio};
+use biliup::client::{Client, LoginInfo};
+use biliup::line::{self, Line};
+use biliup::video::{BiliBili, Video};
 use biliup::VideoFile;
-use futures::StreamExt;
+use meta::StudioMeta;
+use probe::LINE_CACHE;
+use progress::Progress;
 use pyo3::pyclass;
+use retry::RetryPolicy;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::path::PathBuf;
-use std::time::Instant;
-use tracing::info;
+use session::Session;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
 
 #[pyclass]
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum UploadLine {
     Bda2,
     Ws,
@@ -21,89 +39,293 @@ pub enum UploadLine {
     CosInternal,
 }
 
+/// Uploads `video_path` and submits them as one multi-part video described by `meta`, which
+/// is validated before anything is uploaded.
+///
+/// With a `state_file`, progress is persisted there and a re-invoked upload skips the videos
+/// already uploaded and resumes an interrupted upos upload at its last finished chunk.
+#[allow(clippy::too_many_arguments)]
 pub async fn upload(
     video_path: Vec<PathBuf>,
     cookie_file: PathBuf,
     line: Option<UploadLine>,
     limit: usize,
-    title: String,
-    tid: u16,
-    tag: String,
-    copyright: u8,
-    source: String,
-    desc: String,
-    dynamic: String,
-    cover: String,
-    dtime: Option<u32>,
+    meta: StudioMeta,
+    state_file: Option<PathBuf>,
+    retry: RetryPolicy,
+    progress: Progress,
 ) -> Result<Value> {
+    meta.validate(video_path.len())?;
+    let (client, login_info) = login(&cookie_file).await?;
+    let mut session = match &state_file {
+        Some(state_file) => Session::load(state_file)?,
+        None => Session::default(),
+    };
+    let videos = upload_videos(
+        &client,
+        line,
+        video_path,
+        limit,
+        &retry,
+        &progress,
+        &mut session,
+    )
+    .await?;
+    let res = submit(&client, &login_info, &meta, videos).await?;
+    session.finish()?;
+    Ok(res)
+}
+
+/// Uploads a recording while it is still being written and submits it as a single video once
+/// `source` ends, see [`live`] for the sources.
+///
+/// Only upos lines can take a stream of unknown length, without `line` the best probed one
+/// is used.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_live(
+    source: impl futures::Stream<Item = std::io::Result<bytes::Bytes>>,
+    file_name: &str,
+    size_hint: u64,
+    cookie_file: PathBuf,
+    line: Option<UploadLine>,
+    limit: usize,
+    meta: StudioMeta,
+    retry: RetryPolicy,
+    progress: Progress,
+) -> Result<Value> {
+    meta.validate(1)?;
+    let (client, login_info) = login(&cookie_file).await?;
+    let line = match line {
+        Some(line) => line,
+        None => LINE_CACHE
+            .ranked()
+            .await
+            .into_iter()
+            .find(|line| upos::resumable(&line.to_line()))
+            .unwrap_or(UploadLine::Bda2),
+    };
+    let line = line.to_line();
+    if !upos::resumable(&line) {
+        anyhow::bail!("live uploads need an upos line, got {line:?}");
+    }
+    let file_progress = progress.file(file_name, size_hint);
+    let video = upos::upload_live(
+        &client,
+        &line,
+        file_name,
+        size_hint,
+        source,
+        limit,
+        &retry,
+        &file_progress,
+    )
+    .await?;
+    file_progress.finish(&video.filename);
+    submit(&client, &login_info, &meta, vec![video]).await
+}
+
+/// Uploads the cover of `meta` and submits the uploaded `videos`.
+async fn submit(
+    client: &Client,
+    login_info: &LoginInfo,
+    meta: &StudioMeta,
+    videos: Vec<Video>,
+) -> Result<Value> {
+    let mut studio = meta.to_studio(videos)?;
+    if !studio.cover.is_empty() {
+        studio.cover = upload_cover(client, login_info, &studio.cover).await?;
+    }
+    meta.submit(&studio, login_info).await
+}
+
+/// Logs in with the credential file `cookie_file`, refreshing it first if needed.
+pub async fn login(cookie_file: &Path) -> Result<(Client, LoginInfo)> {
+    // Only a rejected token is fatal, the existing one may still work if it could not be checked.
+    if let Err(e) = crate::login::refresh_credentials(cookie_file).await {
+        if e.is::<crate::login::InvalidCredentials>() {
+            return Err(e);
+        }
+        warn!("Unable to refresh {}: {e:#}", cookie_file.display());
+    }
     let client: Client = Default::default();
     let file = std::fs::File::options()
         .read(true)
         .write(true)
-        .open(&cookie_file);
+        .open(cookie_file);
     let login_info = client
         .login_by_cookies(file.with_context(|| cookie_file.to_str().unwrap().to_string())?)
         .await?;
-    let mut videos = Vec::new();
-    let line = match line {
-        Some(UploadLine::Kodo) => line::kodo(),
-        Some(UploadLine::Bda2) => line::bda2(),
-        Some(UploadLine::Ws) => line::ws(),
-        Some(UploadLine::Qn) => line::qn(),
-        Some(UploadLine::Cos) => line::cos(),
-        Some(UploadLine::CosInternal) => line::cos_internal(),
-        None => Probe::probe().await.unwrap_or_default(),
+    Ok((client, login_info))
+}
+
+impl UploadLine {
+    pub const ALL: [UploadLine; 6] = [
+        UploadLine::Bda2,
+        UploadLine::Ws,
+        UploadLine::Qn,
+        UploadLine::Kodo,
+        UploadLine::Cos,
+        UploadLine::CosInternal,
+    ];
+
+    pub fn to_line(self) -> Line {
+        match self {
+            UploadLine::Kodo => line::kodo(),
+            UploadLine::Bda2 => line::bda2(),
+            UploadLine::Ws => line::ws(),
+            UploadLine::Qn => line::qn(),
+            UploadLine::Cos => line::cos(),
+            UploadLine::CosInternal => line::cos_internal(),
+        }
+    }
+}
+
+/// Uploads the files through `line`, or through the best line of the last probe if `None`.
+///
+/// Without an explicit line, an upload that failed on the line is started over on the
+/// next-best line and the failed one is moved to the end of the cached ranking.
+pub async fn upload_videos(
+    client: &Client,
+    line: Option<UploadLine>,
+    video_path: Vec<PathBuf>,
+    limit: usize,
+    retry: &RetryPolicy,
+    progress: &Progress,
+    session: &mut Session,
+) -> Result<Vec<Video>> {
+    let candidates = match line {
+        Some(line) => vec![line],
+        None => LINE_CACHE.ranked().await,
     };
-    // let line = line::kodo();
+    let mut result = Err(anyhow::anyhow!("no upload line available"));
+    for (i, line) in candidates.iter().enumerate() {
+        result = upload_on_line(
+            client,
+            &line.to_line(),
+            video_path.clone(),
+            limit,
+            retry,
+            progress,
+            session,
+        )
+        .await;
+        match &result {
+            Err(e) if i + 1 < candidates.len() && !is_local(e) => {
+                warn!(?line, next = ?candidates[i + 1], "Upload failed, falling back: {e}");
+                LINE_CACHE.demote(*line);
+                // The upload id and endpoint belong to the failed line.
+                session.discard_in_flight()?;
+            }
+            _ => break,
+        }
+    }
+    result
+}
+
+/// Whether `e` is about reading the files or writing the state file rather than about the
+/// line, so that another line would fail the same way.
+fn is_local(e: &anyhow::Error) -> bool {
+    for cause in e.chain() {
+        if cause.is::<reqwest::Error>() {
+            return false;
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            // Reading a response body reports network errors as I/O errors.
+            return !e
+                .get_ref()
+                .is_some_and(|inner| inner.is::<reqwest::Error>());
+        }
+        if let Some(crate::error::Error::File { .. }) = cause.downcast_ref() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Uploads the files one after another, skipping the ones `session` has already completed.
+async fn upload_on_line(
+    client: &Client,
+    line: &Line,
+    video_path: Vec<PathBuf>,
+    limit: usize,
+    retry: &RetryPolicy,
+    progress: &Progress,
+    session: &mut Session,
+) -> Result<Vec<Video>> {
+    let mut videos = Vec::new();
     for video_path in video_path {
-        println!("{:?}", video_path.canonicalize()?.to_str());
-        info!("{line:?}");
-        let video_file = VideoFile::new(&video_path)?;
+        let video_path = video_path
+            .canonicalize()
+            .map_err(crate::error::Error::file(&video_path))?;
+        debug!(path = %video_path.display(), "Uploading file");
+        let video_file =
+            VideoFile::new(&video_path).map_err(crate::error::Error::file(&video_path))?;
         let total_size = video_file.total_size;
         let file_name = video_file.file_name.clone();
-        let uploader = line.to_uploader(video_file);
-
-        let instant = Instant::now();
+        if let Some(video) = session.completed(&video_path, total_size) {
+            info!("Already uploaded: {file_name}");
+            videos.push(video);
+            continue;
+        }
+        info!("{line:?}");
 
-        let video = uploader
-            .upload(&client, limit, |vs| {
-                vs.map(|vs| {
-                    let chunk = vs?;
-                    let len = chunk.len();
-                    Ok((chunk, len))
-                })
-            })
-            .await?;
-        let t = instant.elapsed().as_millis();
+        let file_progress = progress.file(&file_name, total_size);
+        let os = serde_json::to_value(line)?["os"].clone();
+        let video = match os.as_str() {
+            Some("upos") => {
+                upos::upload(
+                    client,
+                    line,
+                    &video_path,
+                    limit,
+                    retry,
+                    &file_progress,
+                    session,
+                )
+                .await?
+            }
+            Some("kodo") => {
+                kodo::upload(client, line, &video_path, limit, retry, &file_progress).await?
+            }
+            Some("cos") => {
+                cos::upload(client, line, &video_path, limit, retry, &file_progress).await?
+            }
+            _ => anyhow::bail!("unsupported upload line {line:?}"),
+        };
+        session.complete(video_path, total_size, &video)?;
+        let summary = file_progress.finish(&video.filename);
         info!(
             "Upload completed: {file_name} => cost {:.2}s, {:.2} MB/s.",
-            t as f64 / 1000.,
-            total_size as f64 / 1000. / t as f64
+            summary.elapsed_secs,
+            summary.throughput / 1e6
         );
         videos.push(video);
     }
-    let mut studio: Studio = Studio::builder()
-        .desc(desc)
-        .dtime(dtime)
-        .copyright(copyright)
-        .cover(cover)
-        .dynamic(dynamic)
-        .source(source)
-        .tag(tag)
-        .tid(tid)
-        .title(title)
-        .videos(videos)
-        .build();
-    if !studio.cover.is_empty() {
-        let url = BiliBili::new(&login_info, &client)
-            .cover_up(
-                &std::fs::read(&studio.cover)
-                    .with_context(|| format!("cover: {}", studio.cover))?,
-            )
-            .await?;
-        println!("{url}");
-        studio.cover = url;
+    Ok(videos)
+}
+
+/// Uploads the image at `cover`, resized if needed, and returns its URL.
+pub async fn upload_cover(client: &Client, login_info: &LoginInfo, cover: &str) -> Result<String> {
+    let url = BiliBili::new(login_info, client)
+        .cover_up(&cover::prepare(Path::new(cover))?)
+        .await?;
+    info!(%url, "Uploaded cover");
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_local;
+    use anyhow::Context;
+
+    #[test]
+    fn local_errors() {
+        let io = || std::io::Error::from(std::io::ErrorKind::NotFound);
+        let file = crate::error::Error::file("a.flv")(io());
+        assert!(is_local(&anyhow::Error::from(file).context("upload")));
+        assert!(is_local(
+            &Err::<(), _>(io()).context("state file").unwrap_err()
+        ));
+        assert!(!is_local(&anyhow::anyhow!("chunk 3 rejected")));
     }
-    Ok(studio.submit(&login_info).await?)
-    // Ok(videos)
 }
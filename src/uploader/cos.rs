@@ -0,0 +1,262 @@
+use crate::uploader::limiter::UPLOAD_LIMITER;
+use crate::uploader::progress::FileProgress;
+use crate::uploader::retry::RetryPolicy;
+use crate::uploader::upos::{https, read_chunk, TIMEOUT};
+use anyhow::{bail, Context, Result};
+use biliup::client::Client;
+use biliup::line::Line;
+use biliup::video::Video;
+use biliup::VideoFile;
+use futures::{StreamExt, TryStreamExt};
+use reqwest::header::{HeaderMap, HeaderName, AUTHORIZATION, CONTENT_LENGTH, ETAG};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use tracing::{info_span, Instrument};
+
+const CHUNK_SIZE: usize = 10 * 1024 * 1024;
+
+/// Headers of the fetch request, taken from the bucket's `fetch_headers`.
+const FETCH_HEADERS: [&str; 3] = [
+    "X-Upos-Fetch-Source",
+    "X-Upos-Auth",
+    "Fetch-Header-Authorization",
+];
+
+/// Upload target handed out by `preupload` for Cos lines.
+#[derive(Debug, Deserialize)]
+struct Bucket {
+    bili_filename: String,
+    fetch_headers: HashMap<String, String>,
+    fetch_url: String,
+    post_auth: String,
+    put_auth: String,
+    url: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Protocol<'a> {
+    upload_id: &'a str,
+    part_number: usize,
+}
+
+/// Uploads `path` through a Cos line, retrying every chunk according to `retry`. `internal`
+/// sends the chunks to the endpoint inside Tencent Cloud. The site fetches the file from Cos
+/// once it is assembled.
+pub async fn upload(
+    client: &Client,
+    line: &Line,
+    path: &Path,
+    limit: usize,
+    retry: &RetryPolicy,
+    progress: &FileProgress<'_>,
+) -> Result<Video> {
+    let internal = serde_json::to_value(line)?["probe_url"] == "internal";
+    let bucket: Bucket = line
+        .to_uploader(VideoFile::new(path)?)
+        .pre_upload(client)
+        .await?;
+    upload_to(&bucket, internal, path, limit, retry, progress).await
+}
+
+async fn upload_to(
+    bucket: &Bucket,
+    internal: bool,
+    path: &Path,
+    limit: usize,
+    retry: &RetryPolicy,
+    progress: &FileProgress<'_>,
+) -> Result<Video> {
+    let size = std::fs::metadata(path)?.len();
+    let client = reqwest::Client::builder()
+        .user_agent("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 Chrome/63.0.3239.108")
+        .timeout(TIMEOUT)
+        .build()?;
+    let client = &client;
+    let res = retry
+        .retry(|| async {
+            client
+                .post(format!("{}?uploads&output=json", bucket.url))
+                .header(AUTHORIZATION, &bucket.post_auth)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        })
+        .await?;
+    let upload_id = res
+        .split_once("<UploadId>")
+        .and_then(|(_, rest)| rest.split_once("</UploadId>"))
+        .map(|(upload_id, _)| upload_id.to_string())
+        .with_context(|| format!("no upload id: {res}"))?;
+    let upload_id = &upload_id;
+    let put_url = match internal {
+        true => bucket
+            .url
+            .replace("cos.accelerate", "cos-internal.ap-shanghai"),
+        false => bucket.url.clone(),
+    };
+    let put_url = &put_url;
+    let chunks = size.div_ceil(CHUNK_SIZE as u64) as usize;
+    let limit = UPLOAD_LIMITER.concurrency(limit, CHUNK_SIZE, TIMEOUT);
+    let mut parts: Vec<(usize, String)> = futures::stream::iter(0..chunks)
+        .map(|chunk| async move {
+            let start = chunk as u64 * CHUNK_SIZE as u64;
+            let len = CHUNK_SIZE.min((size - start) as usize);
+            let body = read_chunk(path, start, len)?;
+            let params = Protocol {
+                upload_id,
+                part_number: chunk + 1,
+            };
+            let res = retry
+                .retry(|| async {
+                    client
+                        .put(put_url)
+                        .header(AUTHORIZATION, &bucket.put_auth)
+                        .header(CONTENT_LENGTH, len)
+                        .query(&params)
+                        .body(UPLOAD_LIMITER.body(body.clone()))
+                        .send()
+                        .await?
+                        .error_for_status()
+                })
+                .instrument(info_span!("chunk", chunk, start))
+                .await?;
+            let Some(etag) = res.headers().get(ETAG).and_then(|etag| etag.to_str().ok()) else {
+                bail!("no ETag for chunk {chunk}: {}", res.text().await?);
+            };
+            progress.add(len as u64);
+            Ok((params.part_number, etag.to_string()))
+        })
+        .buffer_unordered(limit)
+        .try_collect()
+        .await?;
+    parts.sort_unstable();
+    let parts: String = parts
+        .iter()
+        .map(|(number, etag)| {
+            format!("<Part><PartNumber>{number}</PartNumber><ETag>{etag}</ETag></Part>")
+        })
+        .collect();
+    let xml = format!("<CompleteMultipartUpload>{parts}</CompleteMultipartUpload>");
+    retry
+        .retry(|| async {
+            client
+                .post(&bucket.url)
+                .query(&[("uploadId", upload_id)])
+                .header(AUTHORIZATION, &bucket.post_auth)
+                .body(xml.clone())
+                .send()
+                .await?
+                .error_for_status()
+        })
+        .await?;
+    let mut headers = HeaderMap::new();
+    for name in FETCH_HEADERS {
+        let value = bucket
+            .fetch_headers
+            .get(name)
+            .with_context(|| format!("no {name} in the fetch headers"))?;
+        headers.insert(HeaderName::from_str(name)?, value.parse()?);
+    }
+    retry
+        .retry(|| async {
+            client
+                .post(https(&bucket.fetch_url))
+                .headers(headers.clone())
+                .send()
+                .await?
+                .error_for_status()
+        })
+        .await?;
+    let stem = |path: &Path| {
+        path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .map(str::to_string)
+    };
+    Ok(Video {
+        title: stem(path),
+        filename: stem(Path::new(&bucket.bili_filename)).unwrap_or_default(),
+        desc: "".into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{upload_to, Bucket, FETCH_HEADERS};
+    use crate::uploader::progress::Progress;
+    use crate::uploader::retry::RetryPolicy;
+    use std::time::Duration;
+    use wiremock::matchers::{body_string_contains, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn retries_chunks() -> anyhow::Result<()> {
+        let file =
+            std::env::temp_dir().join(format!("stream-gears-cos-{}.mp4", std::process::id()));
+        std::fs::write(&file, b"0123456789")?;
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/n1.mp4"))
+            .and(query_param("uploads", ""))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("<Result><UploadId>id</UploadId></Result>"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(query_param("uploadId", "id"))
+            .and(query_param("partNumber", "1"))
+            .respond_with(ResponseTemplate::new(200).insert_header("ETag", "e1"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/n1.mp4"))
+            .and(query_param("uploadId", "id"))
+            .and(body_string_contains("<ETag>e1</ETag>"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/fetch"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let bucket = Bucket {
+            bili_filename: "n1.mp4".to_string(),
+            fetch_headers: FETCH_HEADERS
+                .iter()
+                .map(|name| (name.to_string(), "value".to_string()))
+                .collect(),
+            fetch_url: format!("{}/fetch", server.uri()),
+            post_auth: "post".to_string(),
+            put_auth: "put".to_string(),
+            url: format!("{}/n1.mp4", server.uri()),
+        };
+        let retry = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+        };
+        let progress = Progress::default();
+        let file_progress = progress.file("", 10);
+        let video = upload_to(&bucket, false, &file, 1, &retry, &file_progress).await?;
+        assert_eq!(video.filename, "n1");
+        assert_eq!(file_progress.finish("n1").uploaded_bytes, 10);
+        std::fs::remove_file(file)?;
+        Ok(())
+    }
+}
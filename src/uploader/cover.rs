@@ -0,0 +1,300 @@
+//! Cover images: supplied covers are validated and resized for `cover_up`, and the first
+//! keyframe of a recording can be extracted for a cover.
+//!
+//! Turning the keyframe into a JPEG needs an H.264 decoder, and there is no pure-Rust one to
+//! depend on yet. Until there is, [`first_keyframe`] stops at the Annex B stream, which
+//! callers decode with their own tools and pass back as `cover`.
+
+use crate::flv_parser::{
+    avc_decoder_configuration_record, avc_video_packet, header, sps_resolution, tag_header,
+    video_data, AVCPacketType, CodecId, FrameType, TagType,
+};
+use anyhow::{bail, Context, Result};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageFormat, Rgb, RgbImage};
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::Path;
+use tracing::info;
+
+/// Covers above this size are recompressed before uploading.
+pub const MAX_BYTES: usize = 2 * 1024 * 1024;
+
+/// Smaller covers are scaled to fit and padded with black bars up to this size, the smallest
+/// the site accepts.
+const MIN_SIZE: (u32, u32) = (960, 600);
+
+/// Larger covers are scaled down to fit.
+const MAX_SIZE: (u32, u32) = (1920, 1200);
+
+/// The first keyframe is expected at the start of a recording, give up after this much.
+const MAX_SCAN: u64 = 64 * 1024 * 1024;
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// Checks that `path` is a JPEG or PNG image, reading only its header.
+pub fn validate(path: &Path) -> Result<()> {
+    let reader = image::io::Reader::open(path)
+        .with_context(|| format!("cover {} does not exist", path.display()))?
+        .with_guessed_format()?;
+    match reader.format() {
+        Some(ImageFormat::Jpeg | ImageFormat::Png) => {}
+        format => bail!(
+            "cover {} must be a JPEG or PNG image, got {format:?}",
+            path.display()
+        ),
+    }
+    reader.into_dimensions()?;
+    Ok(())
+}
+
+/// Validates the cover at `path` and returns it ready for `cover_up`: scaled down if it is
+/// too large, padded if it is too small, and recompressed as JPEG if either happened or the
+/// file is too large.
+pub fn prepare(path: &Path) -> Result<Vec<u8>> {
+    validate(path)?;
+    let bytes = std::fs::read(path)?;
+    let mut image = image::load_from_memory(&bytes)?;
+    let (width, height) = image.dimensions();
+    let oversized = width > MAX_SIZE.0 || height > MAX_SIZE.1;
+    let undersized = width < MIN_SIZE.0 || height < MIN_SIZE.1;
+    if !oversized && !undersized && bytes.len() <= MAX_BYTES {
+        return Ok(bytes);
+    }
+    if oversized {
+        image = image.resize(MAX_SIZE.0, MAX_SIZE.1, FilterType::Triangle);
+    }
+    if image.width() < MIN_SIZE.0 || image.height() < MIN_SIZE.1 {
+        info!(
+            width,
+            height,
+            "Cover {} is smaller than {}x{}, padding it",
+            path.display(),
+            MIN_SIZE.0,
+            MIN_SIZE.1
+        );
+        image = pad(&image);
+    }
+    for quality in [90, 80, 70, 60] {
+        let jpeg = encode_jpeg(&image, quality)?;
+        if jpeg.len() <= MAX_BYTES {
+            info!(
+                width = image.width(),
+                height = image.height(),
+                quality,
+                bytes = jpeg.len(),
+                "Recompressed cover {}",
+                path.display()
+            );
+            return Ok(jpeg);
+        }
+    }
+    bail!(
+        "cover {} cannot be compressed below {MAX_BYTES} bytes",
+        path.display()
+    )
+}
+
+/// Scales `image` to fit [`MIN_SIZE`] and centers it on a black canvas of that size, or of
+/// its own size along a side that is long enough already.
+fn pad(image: &DynamicImage) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let canvas = (width.max(MIN_SIZE.0), height.max(MIN_SIZE.1));
+    let image = match width < MIN_SIZE.0 && height < MIN_SIZE.1 {
+        true => image.resize(MIN_SIZE.0, MIN_SIZE.1, FilterType::Triangle),
+        false => image.clone(),
+    };
+    let mut padded = RgbImage::from_pixel(canvas.0, canvas.1, Rgb([0, 0, 0]));
+    image::imageops::overlay(
+        &mut padded,
+        &image.to_rgb8(),
+        (canvas.0 - image.width()) / 2,
+        (canvas.1 - image.height()) / 2,
+    );
+    DynamicImage::ImageRgb8(padded)
+}
+
+fn encode_jpeg(image: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    let mut jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, quality)
+        .encode_image(&image.to_rgb8())?;
+    Ok(jpeg)
+}
+
+/// The first AVC keyframe of a recording.
+#[derive(Debug)]
+pub struct Keyframe {
+    /// Milliseconds from the start of the recording.
+    pub timestamp: u32,
+    /// Picture size from the sequence parameter set.
+    pub resolution: Option<(u32, u32)>,
+    /// Parameter sets followed by the frame's NAL units as an Annex B byte stream, which
+    /// H.264 decoders take as a `.h264` file.
+    pub annexb: Vec<u8>,
+}
+
+/// Finds the first AVC keyframe of the FLV file at `path`.
+pub fn first_keyframe(path: &Path) -> Result<Keyframe> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut flv_header = [0; 13];
+    reader.read_exact(&mut flv_header)?;
+    header(&flv_header).map_err(|e| anyhow::anyhow!("invalid flv header: {e:?}"))?;
+    // Parameter sets from the sequence header, completed by the keyframe's NAL units.
+    let mut keyframe: Option<Keyframe> = None;
+    let mut length_size = 4;
+    let mut scanned = 0;
+    loop {
+        let mut tag = [0; 11];
+        match reader.read_exact(&mut tag) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                bail!("no AVC keyframe in {}", path.display())
+            }
+            result => result?,
+        }
+        let (_, tag) = tag_header(&tag).map_err(|e| anyhow::anyhow!("invalid tag: {e:?}"))?;
+        let mut data = vec![0; tag.data_size as usize];
+        reader.read_exact(&mut data)?;
+        reader.seek_relative(4)?;
+        scanned += 15 + u64::from(tag.data_size);
+        if scanned > MAX_SCAN {
+            bail!("no AVC keyframe in the first {MAX_SCAN} bytes");
+        }
+        if tag.tag_type != TagType::Video {
+            continue;
+        }
+        let video = match video_data(&data, data.len()) {
+            Ok((_, video)) if video.codec_id == CodecId::H264 => video,
+            _ => continue,
+        };
+        if video.frame_type != FrameType::Key {
+            continue;
+        }
+        let (_, packet) = avc_video_packet(video.video_data, video.video_data.len())
+            .map_err(|e| anyhow::anyhow!("invalid AVC packet: {e:?}"))?;
+        match (packet.packet_type, keyframe.take()) {
+            (AVCPacketType::SequenceHeader, _) => {
+                let (_, record) = avc_decoder_configuration_record(packet.avc_data)
+                    .map_err(|e| anyhow::anyhow!("invalid AVC sequence header: {e:?}"))?;
+                let mut annexb = Vec::new();
+                for nalu in record
+                    .sequence_parameter_sets
+                    .iter()
+                    .chain(&record.picture_parameter_sets)
+                {
+                    annexb.extend_from_slice(&START_CODE);
+                    annexb.extend_from_slice(nalu);
+                }
+                let resolution = record
+                    .sequence_parameter_sets
+                    .first()
+                    .and_then(|sps| sps_resolution(sps));
+                length_size = record.length_size_minus_one as usize + 1;
+                keyframe = Some(Keyframe {
+                    timestamp: 0,
+                    resolution,
+                    annexb,
+                });
+            }
+            (AVCPacketType::NALU, Some(mut keyframe)) => {
+                let mut nalus = packet.avc_data;
+                while !nalus.is_empty() {
+                    let len = nalus
+                        .get(..length_size)
+                        .context("truncated NAL unit")?
+                        .iter()
+                        .fold(0, |len, &b| len << 8 | b as usize);
+                    let nalu = nalus
+                        .get(length_size..length_size + len)
+                        .context("truncated NAL unit")?;
+                    keyframe.annexb.extend_from_slice(&START_CODE);
+                    keyframe.annexb.extend_from_slice(nalu);
+                    nalus = &nalus[length_size + len..];
+                }
+                keyframe.timestamp = tag.timestamp;
+                return Ok(keyframe);
+            }
+            (_, parameter_sets) => keyframe = parameter_sets,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{first_keyframe, prepare, validate, MAX_BYTES};
+    use image::GenericImageView;
+
+    fn tag(tag_type: u8, timestamp: u32, body: &[u8]) -> Vec<u8> {
+        let mut tag = vec![tag_type];
+        tag.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        tag.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+        tag.extend_from_slice(&[0; 4]);
+        tag.extend_from_slice(body);
+        tag.extend_from_slice(&(body.len() as u32 + 11).to_be_bytes());
+        tag
+    }
+
+    #[test]
+    fn keyframe() -> anyhow::Result<()> {
+        let mut flv = b"FLV\x01\x05\x00\x00\x00\x09\x00\x00\x00\x00".to_vec();
+        flv.extend(tag(8, 0, &[0xaf, 0x00, 0x12, 0x10]));
+        // Sequence header with a 4 byte length size, one SPS and one PPS.
+        flv.extend(tag(
+            9,
+            0,
+            &[
+                0x17, 0, 0, 0, 0, 1, 0x64, 0, 0x1f, 0xff, 0xe1, 0, 2, 0x67, 0x64, 1, 0, 2, 0x68,
+                0xee,
+            ],
+        ));
+        flv.extend(tag(9, 40, &[0x27, 1, 0, 0, 0, 0, 0, 0, 1, 0x41]));
+        flv.extend(tag(
+            9,
+            80,
+            &[0x17, 1, 0, 0, 0, 0, 0, 0, 1, 0x06, 0, 0, 0, 2, 0x65, 0x88],
+        ));
+        let path = std::env::temp_dir().join(format!("keyframe-{}.flv", std::process::id()));
+        std::fs::write(&path, flv)?;
+        let keyframe = first_keyframe(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(keyframe.timestamp, 80);
+        assert_eq!(
+            keyframe.annexb,
+            [
+                0, 0, 0, 1, 0x67, 0x64, 0, 0, 0, 1, 0x68, 0xee, 0, 0, 0, 1, 0x06, 0, 0, 0, 1, 0x65,
+                0x88
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resizes_cover() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir();
+        let small = dir.join(format!("cover-small-{}.png", std::process::id()));
+        image::RgbImage::from_pixel(100, 100, image::Rgb([255, 255, 255])).save(&small)?;
+        validate(&small)?;
+        let jpeg = prepare(&small)?;
+        std::fs::remove_file(&small)?;
+        let image = image::load_from_memory(&jpeg)?;
+        assert_eq!((image.width(), image.height()), (960, 600));
+        // Scaled to 600x600 and centered between black bars.
+        assert!(image.get_pixel(10, 300)[0] < 16);
+        assert!(image.get_pixel(480, 300)[0] > 240);
+
+        let wide = dir.join(format!("cover-wide-{}.png", std::process::id()));
+        image::RgbImage::new(1200, 300).save(&wide)?;
+        let image = image::load_from_memory(&prepare(&wide)?)?;
+        std::fs::remove_file(&wide)?;
+        assert_eq!((image.width(), image.height()), (1200, 600));
+
+        let large = dir.join(format!("cover-large-{}.png", std::process::id()));
+        image::RgbImage::from_fn(2400, 1350, |x, y| image::Rgb([x as u8, y as u8, 0]))
+            .save(&large)?;
+        let jpeg = prepare(&large)?;
+        std::fs::remove_file(&large)?;
+        assert!(jpeg.len() <= MAX_BYTES);
+        let image = image::load_from_memory(&jpeg)?;
+        assert_eq!((image.width(), image.height()), (1920, 1080));
+        Ok(())
+    }
+}
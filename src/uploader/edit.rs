@@ -0,0 +1,273 @@
+use crate::exceptions::upload_error;
+use crate::runtime::block_on_py;
+use crate::uploader::progress::Progress;
+use crate::uploader::retry::RetryPolicy;
+use crate::uploader::session::Session;
+use crate::uploader::{login, upload_cover, upload_videos, UploadLine};
+use anyhow::{bail, Result};
+use biliup::client::{Client, LoginInfo};
+use biliup::video::{BiliBili, Studio, Vid, Video};
+use pyo3::prelude::*;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// An existing submission loaded by its BV or AV id, edited in place and resubmitted through
+/// the edit endpoint.
+#[pyclass]
+pub struct Submission {
+    studio: Studio,
+    client: Client,
+    login_info: LoginInfo,
+    /// Tracks the appended videos until the edit is submitted.
+    session: Session,
+}
+
+impl Submission {
+    /// Loads the submission `vid`, e.g. `BV1ip4y1x7Gi`, `av971158452` or `971158452`.
+    pub async fn load(cookie_file: &Path, vid: &str) -> Result<Self> {
+        let vid = parse_vid(vid)?;
+        let (client, login_info) = login(cookie_file).await?;
+        let studio = BiliBili::new(&login_info, &client).studio_data(vid).await?;
+        Ok(Self {
+            studio,
+            client,
+            login_info,
+            session: Session::default(),
+        })
+    }
+
+    pub fn studio(&self) -> &Studio {
+        &self.studio
+    }
+
+    pub fn studio_mut(&mut self) -> &mut Studio {
+        &mut self.studio
+    }
+
+    /// Removes the part at `index`, counting from 0.
+    pub fn remove(&mut self, index: usize) -> Result<Video> {
+        if index >= self.studio.videos.len() {
+            bail!(
+                "part {index} out of range, the submission has {} parts",
+                self.studio.videos.len()
+            );
+        }
+        Ok(self.studio.videos.remove(index))
+    }
+
+    /// Uploads `video_path` and appends them as new parts, see [`upload_videos`] for `line`.
+    ///
+    /// With a `state_file` the uploads are resumable until [`Submission::submit`] succeeds.
+    pub async fn append(
+        &mut self,
+        video_path: Vec<PathBuf>,
+        line: Option<UploadLine>,
+        limit: usize,
+        retry: &RetryPolicy,
+        progress: &Progress,
+        state_file: Option<&Path>,
+    ) -> Result<()> {
+        if let Some(state_file) = state_file {
+            self.session = Session::load(state_file)?;
+        }
+        let videos = upload_videos(
+            &self.client,
+            line,
+            video_path,
+            limit,
+            retry,
+            progress,
+            &mut self.session,
+        )
+        .await?;
+        self.studio.videos.extend(videos);
+        Ok(())
+    }
+
+    /// Submits the edited submission, a local cover file is uploaded first.
+    pub async fn submit(&mut self) -> Result<Value> {
+        if self.studio.title.trim().is_empty() {
+            bail!("title must not be empty");
+        }
+        if self.studio.videos.is_empty() {
+            bail!("a submission needs at least one part");
+        }
+        if !self.studio.cover.is_empty() && Path::new(&self.studio.cover).is_file() {
+            self.studio.cover =
+                upload_cover(&self.client, &self.login_info, &self.studio.cover).await?;
+        }
+        let res = self.studio.edit(&self.login_info).await?;
+        std::mem::take(&mut self.session).finish()?;
+        Ok(res)
+    }
+}
+
+fn parse_vid(vid: &str) -> Result<Vid> {
+    let vid = vid.trim();
+    // biliup slices off the first two bytes to tell an aid from a bvid.
+    if vid.len() < 2 || !vid.is_char_boundary(2) {
+        bail!("invalid video id {vid:?}");
+    }
+    Ok(vid.parse()?)
+}
+
+#[pymethods]
+impl Submission {
+    #[staticmethod]
+    #[pyo3(name = "load")]
+    fn py_load(py: Python<'_>, cookie_file: PathBuf, vid: &str) -> PyResult<Self> {
+        py.allow_threads(|| block_on_py(Submission::load(&cookie_file, vid)))
+    }
+
+    #[getter]
+    fn aid(&self) -> Option<u64> {
+        self.studio.aid
+    }
+
+    #[getter]
+    fn title(&self) -> &str {
+        &self.studio.title
+    }
+
+    #[getter]
+    fn desc(&self) -> &str {
+        &self.studio.desc
+    }
+
+    #[getter]
+    fn tag(&self) -> &str {
+        &self.studio.tag
+    }
+
+    #[getter]
+    fn tid(&self) -> u16 {
+        self.studio.tid
+    }
+
+    #[getter]
+    fn cover(&self) -> &str {
+        &self.studio.cover
+    }
+
+    /// Changes the given fields, `cover` may be a URL or a local image to upload on submit.
+    #[args(
+        title = "None",
+        desc = "None",
+        tag = "None",
+        tid = "None",
+        cover = "None",
+        dynamic = "None",
+        source = "None",
+        copyright = "None"
+    )]
+    #[allow(clippy::too_many_arguments)]
+    fn edit(
+        &mut self,
+        title: Option<String>,
+        desc: Option<String>,
+        tag: Option<String>,
+        tid: Option<u16>,
+        cover: Option<String>,
+        dynamic: Option<String>,
+        source: Option<String>,
+        copyright: Option<u8>,
+    ) {
+        let studio = &mut self.studio;
+        studio.title = title.unwrap_or(std::mem::take(&mut studio.title));
+        studio.desc = desc.unwrap_or(std::mem::take(&mut studio.desc));
+        studio.tag = tag.unwrap_or(std::mem::take(&mut studio.tag));
+        studio.tid = tid.unwrap_or(studio.tid);
+        studio.cover = cover.unwrap_or(std::mem::take(&mut studio.cover));
+        studio.dynamic = dynamic.unwrap_or(std::mem::take(&mut studio.dynamic));
+        studio.source = source.unwrap_or(std::mem::take(&mut studio.source));
+        studio.copyright = copyright.unwrap_or(studio.copyright);
+    }
+
+    /// `(title, filename)` of every part.
+    fn parts(&self) -> Vec<(Option<String>, String)> {
+        self.studio
+            .videos
+            .iter()
+            .map(|video| (video.title.clone(), video.filename.clone()))
+            .collect()
+    }
+
+    fn remove_part(&mut self, index: usize) -> PyResult<()> {
+        self.remove(index)
+            .map(|_| ())
+            .map_err(|e| pyo3::exceptions::PyIndexError::new_err(e.to_string()))
+    }
+
+    fn set_part_title(&mut self, index: usize, title: String) -> PyResult<()> {
+        match self.studio.videos.get_mut(index) {
+            Some(video) => {
+                video.title = Some(title);
+                Ok(())
+            }
+            None => Err(pyo3::exceptions::PyIndexError::new_err(format!(
+                "part {index} out of range"
+            ))),
+        }
+    }
+
+    /// Uploads `video_path` and appends them as new parts, titled by `part_titles` if given.
+    #[pyo3(name = "append")]
+    #[args(
+        line = "None",
+        limit = "3",
+        part_titles = "Vec::new()",
+        state_file = "None"
+    )]
+    fn py_append(
+        &mut self,
+        py: Python<'_>,
+        video_path: Vec<PathBuf>,
+        line: Option<UploadLine>,
+        limit: usize,
+        part_titles: Vec<String>,
+        state_file: Option<PathBuf>,
+    ) -> PyResult<()> {
+        let start = self.studio.videos.len();
+        py.allow_threads(|| {
+            crate::runtime::block_on(self.append(
+                video_path,
+                line,
+                limit,
+                &RetryPolicy::default(),
+                &Progress::default(),
+                state_file.as_deref(),
+            ))
+            .map_err(upload_error)
+        })?;
+        for (video, title) in self.studio.videos[start..].iter_mut().zip(part_titles) {
+            video.title = Some(title);
+        }
+        Ok(())
+    }
+
+    /// Submits the changes and returns the server response as JSON.
+    #[pyo3(name = "submit")]
+    fn py_submit(&mut self, py: Python<'_>) -> PyResult<String> {
+        py.allow_threads(|| crate::runtime::block_on(self.submit()).map_err(upload_error))
+            .map(|res| res.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_vid;
+    use biliup::video::Vid;
+
+    #[test]
+    fn vid() {
+        assert_eq!(
+            parse_vid(" BV1ip4y1x7Gi ").unwrap(),
+            Vid::Bvid("BV1ip4y1x7Gi".to_string())
+        );
+        assert_eq!(parse_vid("av971158452").unwrap(), Vid::Aid(971158452));
+        assert!(parse_vid("a").is_err());
+        assert!(parse_vid("avx").is_err());
+        assert!(parse_vid("中").is_err());
+        assert!(parse_vid("a中").is_err());
+    }
+}
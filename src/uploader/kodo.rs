@@ -0,0 +1,203 @@
+use crate::uploader::limiter::UPLOAD_LIMITER;
+use crate::uploader::progress::FileProgress;
+use crate::uploader::retry::RetryPolicy;
+use crate::uploader::upos::{https, read_chunk, TIMEOUT};
+use anyhow::{bail, Result};
+use biliup::client::Client;
+use biliup::line::Line;
+use biliup::video::Video;
+use biliup::VideoFile;
+use futures::{StreamExt, TryStreamExt};
+use reqwest::header::{self, HeaderMap, HeaderName, CONTENT_LENGTH};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use tracing::{info_span, Instrument};
+
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Upload target handed out by `preupload` for Kodo lines.
+#[derive(Debug, Deserialize)]
+struct Bucket {
+    bili_filename: String,
+    fetch_url: String,
+    endpoint: String,
+    uptoken: String,
+    key: String,
+    fetch_headers: HashMap<String, String>,
+}
+
+/// Uploads `path` through a Kodo line, retrying every chunk according to `retry`. The site
+/// fetches the file from Kodo once it is assembled.
+pub async fn upload(
+    client: &Client,
+    line: &Line,
+    path: &Path,
+    limit: usize,
+    retry: &RetryPolicy,
+    progress: &FileProgress<'_>,
+) -> Result<Video> {
+    let bucket: Bucket = line
+        .to_uploader(VideoFile::new(path)?)
+        .pre_upload(client)
+        .await?;
+    upload_to(&bucket, path, limit, retry, progress).await
+}
+
+async fn upload_to(
+    bucket: &Bucket,
+    path: &Path,
+    limit: usize,
+    retry: &RetryPolicy,
+    progress: &FileProgress<'_>,
+) -> Result<Video> {
+    let size = std::fs::metadata(path)?.len();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        format!("UpToken {}", bucket.uptoken).parse()?,
+    );
+    let client = reqwest::Client::builder()
+        .user_agent("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 Chrome/63.0.3239.108")
+        .default_headers(headers)
+        .timeout(TIMEOUT)
+        .build()?;
+    let endpoint = https(&bucket.endpoint);
+    let chunks = size.div_ceil(CHUNK_SIZE as u64) as usize;
+    let limit = UPLOAD_LIMITER.concurrency(limit, CHUNK_SIZE, TIMEOUT);
+    let (client, endpoint) = (&client, &endpoint);
+    let mut ctxs: Vec<(usize, String)> = futures::stream::iter(0..chunks)
+        .map(|chunk| async move {
+            let start = chunk as u64 * CHUNK_SIZE as u64;
+            let len = CHUNK_SIZE.min((size - start) as usize);
+            let body = read_chunk(path, start, len)?;
+            let res: serde_json::Value = retry
+                .retry(|| async {
+                    client
+                        .post(format!("{endpoint}/mkblk/{len}"))
+                        .header(CONTENT_LENGTH, len)
+                        .body(UPLOAD_LIMITER.body(body.clone()))
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .json()
+                        .await
+                })
+                .instrument(info_span!("chunk", chunk, start))
+                .await?;
+            let Some(ctx) = res["ctx"].as_str() else {
+                bail!("no ctx for chunk {chunk}: {res}");
+            };
+            progress.add(len as u64);
+            Ok((chunk, ctx.to_string()))
+        })
+        .buffer_unordered(limit)
+        .try_collect()
+        .await?;
+    ctxs.sort_unstable();
+    let ctxs: Vec<_> = ctxs.into_iter().map(|(_, ctx)| ctx).collect();
+    let key = base64::encode_config(&bucket.key, base64::URL_SAFE);
+    retry
+        .retry(|| async {
+            client
+                .post(format!("{endpoint}/mkfile/{size}/key/{key}"))
+                .body(ctxs.join(","))
+                .send()
+                .await?
+                .error_for_status()
+        })
+        .await?;
+    let mut headers = HeaderMap::new();
+    for (name, value) in &bucket.fetch_headers {
+        headers.insert(HeaderName::from_str(name)?, value.parse()?);
+    }
+    let res: serde_json::Value = retry
+        .retry(|| async {
+            client
+                .post(https(&bucket.fetch_url))
+                .headers(headers.clone())
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+        })
+        .await?;
+    if res.get("OK").is_some_and(|ok| ok != 1) {
+        bail!("failed to fetch upload from kodo: {res}");
+    }
+    Ok(Video {
+        title: path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .map(str::to_string),
+        filename: bucket.bili_filename.clone(),
+        desc: "".into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{upload_to, Bucket};
+    use crate::uploader::progress::Progress;
+    use crate::uploader::retry::RetryPolicy;
+    use std::time::Duration;
+    use wiremock::matchers::{body_string, method, path, path_regex};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn retries_chunks() -> anyhow::Result<()> {
+        let file =
+            std::env::temp_dir().join(format!("stream-gears-kodo-{}.mp4", std::process::id()));
+        std::fs::write(&file, b"0123456789")?;
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/mkblk/10"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/mkblk/10"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"ctx": "c0"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex("^/mkfile/10/key/"))
+            .and(body_string("c0"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/fetch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"OK": 1})))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let bucket = Bucket {
+            bili_filename: "n1".to_string(),
+            fetch_url: format!("{}/fetch", server.uri()),
+            endpoint: server.uri(),
+            uptoken: "token".to_string(),
+            key: "key".to_string(),
+            fetch_headers: Default::default(),
+        };
+        let retry = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+        };
+        let progress = Progress::default();
+        let file_progress = progress.file("", 10);
+        let video = upload_to(&bucket, &file, 2, &retry, &file_progress).await?;
+        assert_eq!(video.filename, "n1");
+        assert_eq!(file_progress.finish("n1").uploaded_bytes, 10);
+        std::fs::remove_file(file)?;
+        Ok(())
+    }
+}
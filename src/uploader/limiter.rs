@@ -0,0 +1,160 @@
+use bytes::Bytes;
+use futures::Stream;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Bandwidth shared by all uploads of the process, see [`RateLimiter`].
+pub static UPLOAD_LIMITER: RateLimiter = RateLimiter::new();
+
+/// Request bodies are throttled in slices of this size, so that a large chunk does not go out
+/// as one burst at full speed.
+const SLICE: usize = 64 * 1024;
+
+/// Caps the bytes per second sent by every concurrent upload together.
+///
+/// Each slice reserves its transmission time on a shared clock, so the concurrent chunks
+/// allowed by `limit` split the budget between them instead of multiplying it.
+pub struct RateLimiter {
+    state: Mutex<State>,
+}
+
+struct State {
+    /// 0 means unlimited.
+    bytes_per_sec: u64,
+    /// When the budget reserved so far is used up.
+    next: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                bytes_per_sec: 0,
+                next: None,
+            }),
+        }
+    }
+
+    /// Sets the limit, `None` or 0 removes it. Takes effect for the next slice being sent.
+    pub fn set_rate(&self, bytes_per_sec: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        state.bytes_per_sec = bytes_per_sec.unwrap_or(0);
+        state.next = None;
+        info!(
+            bytes_per_sec = state.bytes_per_sec,
+            "Upload rate limit changed"
+        );
+    }
+
+    pub fn rate(&self) -> Option<u64> {
+        Some(self.state.lock().unwrap().bytes_per_sec).filter(|&rate| rate > 0)
+    }
+
+    /// Waits until `bytes` may be sent.
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            if state.bytes_per_sec == 0 {
+                return;
+            }
+            let now = Instant::now();
+            let start = state.next.map_or(now, |next| next.max(now));
+            state.next =
+                Some(start + Duration::from_secs_f64(bytes as f64 / state.bytes_per_sec as f64));
+            start - now
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await
+        }
+    }
+
+    /// Concurrency to use for chunks of `chunk_size` bytes when up to `limit` were requested.
+    ///
+    /// A chunk has to make it through within `timeout`, so under a tight limit fewer chunks are
+    /// sent in parallel to leave every one of them enough bandwidth.
+    pub fn concurrency(&self, limit: usize, chunk_size: usize, timeout: Duration) -> usize {
+        let rate = match self.rate() {
+            Some(rate) => rate,
+            None => return limit,
+        };
+        // Aim at half the timeout to leave room for retransmissions.
+        let affordable = (rate as f64 * timeout.as_secs_f64() / 2. / chunk_size as f64) as usize;
+        let concurrency = limit.min(affordable).max(1);
+        if concurrency < limit {
+            info!(
+                limit,
+                concurrency, rate, "Reduced upload concurrency to fit the rate limit"
+            );
+        }
+        concurrency
+    }
+
+    /// Request body of a chunk, throttled if a rate is set.
+    pub fn body(&'static self, body: Bytes) -> reqwest::Body {
+        match self.rate() {
+            Some(_) => reqwest::Body::wrap_stream(self.throttle(body)),
+            None => body.into(),
+        }
+    }
+
+    /// Turns `body` into a stream of slices that are released at the limited rate.
+    pub fn throttle(
+        &'static self,
+        body: Bytes,
+    ) -> impl Stream<Item = std::io::Result<Bytes>> + Send + 'static {
+        futures::stream::unfold(body, move |mut body| async move {
+            if body.is_empty() {
+                return None;
+            }
+            let slice = body.split_to(SLICE.min(body.len()));
+            self.acquire(slice.len() as u64).await;
+            Some((Ok(slice), body))
+        })
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn limits_rate() {
+        let limiter = RateLimiter::new();
+        let instant = Instant::now();
+        limiter.acquire(1_000_000).await;
+        assert!(instant.elapsed() < Duration::from_millis(100));
+
+        limiter.set_rate(Some(10_000));
+        let instant = Instant::now();
+        // Two concurrent uploads share the budget: 3000 bytes take 300ms in total.
+        futures::join!(
+            async {
+                limiter.acquire(1000).await;
+                limiter.acquire(1000).await;
+            },
+            limiter.acquire(1000),
+        );
+        limiter.acquire(0).await;
+        let elapsed = instant.elapsed();
+        assert!(elapsed >= Duration::from_millis(290), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(600), "{elapsed:?}");
+
+        assert_eq!(
+            limiter.concurrency(3, 4 * 1024 * 1024, Duration::from_secs(300)),
+            1
+        );
+        limiter.set_rate(None);
+        assert_eq!(
+            limiter.concurrency(3, 4 * 1024 * 1024, Duration::from_secs(300)),
+            3
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use crate::exceptions::upload_error;
+use crate::uploader::meta::StudioMeta;
+use crate::uploader::progress::Progress;
+use crate::uploader::retry::RetryPolicy;
+use crate::uploader::{upload_live, UploadLine};
+use bytes::Bytes;
+use futures::Stream;
+use pyo3::prelude::*;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
+/// Bytes read from a growing file at a time.
+const READ_SIZE: usize = 1024 * 1024;
+
+/// Reads a file that is still being written, until the writer is done.
+///
+/// The writer is done once `finished` is set or, for a `.part` file, once it was renamed to
+/// its final name. Reading goes on through the open handle after the rename.
+pub fn tail(
+    path: &Path,
+    finished: Arc<AtomicBool>,
+    poll_interval: Duration,
+) -> io::Result<impl Stream<Item = io::Result<Bytes>>> {
+    let file = tokio::fs::File::from_std(std::fs::File::open(path)?);
+    let path = path.to_path_buf();
+    let done = move || {
+        finished.load(Ordering::Relaxed)
+            || (path.extension().is_some_and(|ext| ext == "part") && !path.exists())
+    };
+    Ok(futures::stream::unfold(
+        (Some(file), false),
+        move |(file, mut draining)| {
+            let done = done.clone();
+            async move {
+                let mut file = file?;
+                let mut buf = vec![0; READ_SIZE];
+                loop {
+                    match file.read(&mut buf).await {
+                        Ok(0) if draining => return None,
+                        // Whatever was written before the signal still has to be read.
+                        Ok(0) if done() => draining = true,
+                        Ok(0) => tokio::time::sleep(poll_interval).await,
+                        Ok(n) => {
+                            buf.truncate(n);
+                            return Some((Ok(buf.into()), (Some(file), draining)));
+                        }
+                        Err(e) => return Some((Err(e), (None, draining))),
+                    }
+                }
+            }
+        },
+    ))
+}
+
+/// Uploads a recording from its `.part` file while it is being written, and submits it once
+/// the file is renamed or `finish` is called.
+#[pyclass]
+pub struct LiveUpload {
+    finished: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<anyhow::Result<serde_json::Value>>>,
+}
+
+// pyo3 0.16 nests the `#[new]` wrapper in a static, outside the scope of an `allow` on the
+// impl itself.
+#[allow(non_local_definitions)]
+const _: () = {
+    #[pymethods]
+    impl LiveUpload {
+        /// `meta` is a `StudioMeta` or a dict of its keyword arguments. The file is polled every
+        /// `poll_interval` seconds, `size_hint` defaults to its current size.
+        #[new]
+        #[args(line = "None", limit = "3", poll_interval = "1.0", size_hint = "None")]
+        fn new(
+            path: PathBuf,
+            cookie_file: PathBuf,
+            meta: &PyAny,
+            line: Option<UploadLine>,
+            limit: usize,
+            poll_interval: f64,
+            size_hint: Option<u64>,
+        ) -> PyResult<Self> {
+            let meta = StudioMeta::from_py(meta)?;
+            meta.validate(1)
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+            let finished = Arc::new(AtomicBool::new(false));
+            let source = tail(
+                &path,
+                finished.clone(),
+                crate::seconds("poll_interval", poll_interval)?,
+            )?;
+            let size_hint = match size_hint {
+                Some(size_hint) => size_hint,
+                None => std::fs::metadata(&path)?.len(),
+            };
+            let file_name = match path.extension() {
+                Some(ext) if ext == "part" => path.with_extension(""),
+                _ => path.clone(),
+            };
+            let file_name = file_name
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            crate::logging::init();
+            let span = tracing::info_span!("live_upload", file = %file_name);
+            let thread = std::thread::spawn(move || {
+                let _entered = span.enter();
+                crate::runtime::block_on(upload_live(
+                    source,
+                    &file_name,
+                    size_hint,
+                    cookie_file,
+                    line,
+                    limit,
+                    meta,
+                    RetryPolicy::default(),
+                    Progress::default(),
+                ))
+            });
+            Ok(Self {
+                finished,
+                thread: Some(thread),
+            })
+        }
+
+        /// Signals that the file is complete, for files that are not renamed when done.
+        fn finish(&self) {
+            self.finished.store(true, Ordering::Relaxed)
+        }
+
+        #[getter]
+        fn done(&self) -> bool {
+            match &self.thread {
+                Some(thread) => thread.is_finished(),
+                None => true,
+            }
+        }
+
+        /// Waits for the submission and returns the server response as JSON.
+        fn wait(&mut self, py: Python<'_>) -> PyResult<String> {
+            let thread = self.thread.take().ok_or_else(|| {
+                pyo3::exceptions::PyRuntimeError::new_err("live upload already waited for")
+            })?;
+            py.allow_threads(|| thread.join())
+                .map_err(|_| pyo3::exceptions::PyRuntimeError::new_err("live upload panicked"))?
+                .map(|res| res.to_string())
+                .map_err(upload_error)
+        }
+    }
+};
+
+#[cfg(test)]
+mod tests {
+    use super::tail;
+    use futures::TryStreamExt;
+    use std::io::Write;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn tails_until_renamed() -> anyhow::Result<()> {
+        let part = std::env::temp_dir().join(format!("live-{}.flv.part", std::process::id()));
+        std::fs::write(&part, b"FLV")?;
+        let stream = tail(
+            &part,
+            Arc::new(AtomicBool::new(false)),
+            Duration::from_millis(10),
+        )?;
+        let writer = tokio::task::spawn_blocking({
+            let part = part.clone();
+            move || -> std::io::Result<()> {
+                std::thread::sleep(Duration::from_millis(50));
+                std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&part)?
+                    .write_all(b" tags")?;
+                std::fs::rename(&part, part.with_extension(""))
+            }
+        });
+        let bytes = stream.map_ok(|b| b.to_vec()).try_concat().await?;
+        writer.await??;
+        assert_eq!(bytes, b"FLV tags");
+        std::fs::remove_file(part.with_extension(""))?;
+        Ok(())
+    }
+}
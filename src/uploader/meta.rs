@@ -0,0 +1,391 @@
+use crate::uploader::cover;
+use anyhow::{bail, Result};
+use biliup::client::LoginInfo;
+use biliup::video::{Studio, Video};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Scheduled submissions have to be at least this far in the future.
+const MIN_DELAY: Duration = Duration::from_secs(4 * 60 * 60);
+
+/// Everything about a submission except the videos, mapped onto [`Studio`].
+#[pyclass]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StudioMeta {
+    #[pyo3(get, set)]
+    pub title: String,
+    /// Category id.
+    #[pyo3(get, set)]
+    pub tid: u16,
+    /// Comma separated tags.
+    #[pyo3(get, set)]
+    pub tag: String,
+    /// 1 for original content, 2 for reposts which need a `source`.
+    #[pyo3(get, set)]
+    pub copyright: u8,
+    #[pyo3(get, set)]
+    pub source: String,
+    #[pyo3(get, set)]
+    pub desc: String,
+    #[pyo3(get, set)]
+    pub dynamic: String,
+    /// Path of the cover image, uploaded before submitting.
+    #[pyo3(get, set)]
+    pub cover: String,
+    /// Unix timestamp to publish at, at least 4 hours ahead.
+    #[pyo3(get, set)]
+    pub dtime: Option<u32>,
+    /// Titles of the parts in upload order, missing ones default to the file name.
+    #[pyo3(get, set)]
+    pub part_titles: Vec<String>,
+    #[pyo3(get, set)]
+    pub subtitle_open: bool,
+    #[pyo3(get, set)]
+    pub subtitle_lang: String,
+    #[pyo3(get, set)]
+    pub no_reprint: bool,
+    #[pyo3(get, set)]
+    pub open_elec: bool,
+    #[pyo3(get, set)]
+    pub mission_id: Option<u32>,
+    #[pyo3(get, set)]
+    pub topic_id: Option<u32>,
+    #[pyo3(get, set)]
+    pub dolby: bool,
+    #[pyo3(get, set)]
+    pub up_selection_reply: bool,
+    #[pyo3(get, set)]
+    pub up_close_reply: bool,
+    #[pyo3(get, set)]
+    pub up_close_danmu: bool,
+}
+
+/// The same defaults as the Python constructor, also used for keys missing in a dict.
+impl Default for StudioMeta {
+    fn default() -> Self {
+        Self {
+            title: String::new(),
+            tid: 171,
+            tag: String::new(),
+            copyright: 1,
+            source: String::new(),
+            desc: String::new(),
+            dynamic: String::new(),
+            cover: String::new(),
+            dtime: None,
+            part_titles: Vec::new(),
+            subtitle_open: false,
+            subtitle_lang: String::new(),
+            no_reprint: false,
+            open_elec: false,
+            mission_id: None,
+            topic_id: None,
+            dolby: false,
+            up_selection_reply: false,
+            up_close_reply: false,
+            up_close_danmu: false,
+        }
+    }
+}
+
+// pyo3 0.16 nests the `#[new]` wrapper in a static, outside the scope of an `allow` on the
+// impl itself.
+#[allow(non_local_definitions)]
+const _: () = {
+    #[pymethods]
+    impl StudioMeta {
+        #[new]
+        #[args(
+            tid = "171",
+            tag = "String::new()",
+            copyright = "1",
+            source = "String::new()",
+            desc = "String::new()",
+            dynamic = "String::new()",
+            cover = "String::new()",
+            dtime = "None",
+            part_titles = "Vec::new()",
+            subtitle_open = "false",
+            subtitle_lang = "String::new()",
+            no_reprint = "false",
+            open_elec = "false",
+            mission_id = "None",
+            topic_id = "None",
+            dolby = "false",
+            up_selection_reply = "false",
+            up_close_reply = "false",
+            up_close_danmu = "false"
+        )]
+        #[allow(clippy::too_many_arguments)]
+        fn new(
+            title: String,
+            tid: u16,
+            tag: String,
+            copyright: u8,
+            source: String,
+            desc: String,
+            dynamic: String,
+            cover: String,
+            dtime: Option<u32>,
+            part_titles: Vec<String>,
+            subtitle_open: bool,
+            subtitle_lang: String,
+            no_reprint: bool,
+            open_elec: bool,
+            mission_id: Option<u32>,
+            topic_id: Option<u32>,
+            dolby: bool,
+            up_selection_reply: bool,
+            up_close_reply: bool,
+            up_close_danmu: bool,
+        ) -> Self {
+            Self {
+                title,
+                tid,
+                tag,
+                copyright,
+                source,
+                desc,
+                dynamic,
+                cover,
+                dtime,
+                part_titles,
+                subtitle_open,
+                subtitle_lang,
+                no_reprint,
+                open_elec,
+                mission_id,
+                topic_id,
+                dolby,
+                up_selection_reply,
+                up_close_reply,
+                up_close_danmu,
+            }
+        }
+
+        /// Raises `ValueError` if the submission would be rejected, `parts` is the number of videos.
+        #[pyo3(name = "validate")]
+        #[args(parts = "None")]
+        fn py_validate(&self, parts: Option<usize>) -> PyResult<()> {
+            self.validate(parts.unwrap_or(self.part_titles.len()))
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+        }
+
+        fn __repr__(&self) -> String {
+            format!("{self:?}")
+        }
+    }
+};
+
+impl StudioMeta {
+    /// Accepts a `StudioMeta` or a dict of its keyword arguments.
+    pub fn from_py(meta: &PyAny) -> PyResult<Self> {
+        match meta.downcast::<PyDict>() {
+            Ok(kwargs) => meta
+                .py()
+                .get_type::<StudioMeta>()
+                .call((), Some(kwargs))?
+                .extract(),
+            Err(_) => meta.extract(),
+        }
+    }
+
+    /// Checks the fields before any bytes are uploaded.
+    pub fn validate(&self, parts: usize) -> Result<()> {
+        let chars = |s: &str| s.chars().count();
+        if self.title.trim().is_empty() {
+            bail!("title must not be empty");
+        }
+        if chars(&self.title) > 80 {
+            bail!("title must be at most 80 characters");
+        }
+        match self.copyright {
+            1 => {}
+            2 if self.source.trim().is_empty() => bail!("source is required for reposts"),
+            2 if self.no_reprint => bail!("no_reprint only applies to original content"),
+            2 => {}
+            copyright => bail!("copyright must be 1 or 2, got {copyright}"),
+        }
+        if chars(&self.source) > 200 {
+            bail!("source must be at most 200 characters");
+        }
+        let tags: Vec<&str> = self.tag.split(',').map(str::trim).collect();
+        if tags.iter().any(|tag| tag.is_empty()) {
+            bail!("tag must be a comma separated list of non-empty tags");
+        }
+        if tags.len() > 12 {
+            bail!("at most 12 tags are allowed");
+        }
+        if let Some(tag) = tags.iter().find(|tag| chars(tag) > 20) {
+            bail!("tag {tag:?} is longer than 20 characters");
+        }
+        if chars(&self.desc) > 2000 {
+            bail!("desc must be at most 2000 characters");
+        }
+        if let Some(dtime) = self.dtime {
+            let earliest = SystemTime::now().duration_since(UNIX_EPOCH)? + MIN_DELAY;
+            if u64::from(dtime) < earliest.as_secs() {
+                bail!("dtime must be at least 4 hours in the future");
+            }
+        }
+        if !self.cover.is_empty() {
+            cover::validate(Path::new(&self.cover))?;
+        }
+        if self.part_titles.len() > parts {
+            bail!(
+                "{} part titles given for {parts} videos",
+                self.part_titles.len()
+            );
+        }
+        if let Some(title) = self.part_titles.iter().find(|title| chars(title) > 80) {
+            bail!("part title {title:?} is longer than 80 characters");
+        }
+        if self.subtitle_open && self.subtitle_lang.is_empty() {
+            bail!("subtitle_lang is required when subtitle_open is set");
+        }
+        Ok(())
+    }
+
+    /// Builds the submission for the uploaded `videos`, the cover has to be uploaded already.
+    pub fn to_studio(&self, mut videos: Vec<Video>) -> Result<Studio> {
+        for (video, title) in videos.iter_mut().zip(&self.part_titles) {
+            video.title = Some(title.clone());
+        }
+        let mut studio = Studio::builder()
+            .desc(self.desc.clone())
+            .dtime(self.dtime)
+            .copyright(self.copyright)
+            .cover(self.cover.clone())
+            .dynamic(self.dynamic.clone())
+            .source(self.source.clone())
+            .tag(self.tag.clone())
+            .tid(self.tid)
+            .title(self.title.clone())
+            .videos(videos)
+            .mission_id(self.mission_id)
+            .dolby(self.dolby as u8)
+            .no_reprint(Some(self.no_reprint as u8))
+            .open_elec(Some(self.open_elec as u8))
+            .up_selection_reply(self.up_selection_reply)
+            .up_close_reply(self.up_close_reply)
+            .up_close_danmu(self.up_close_danmu)
+            .open_subtitle(self.subtitle_open)
+            .build();
+        studio.subtitle = serde_json::from_value(json!({
+            "open": self.subtitle_open as i8,
+            "lan": self.subtitle_lang,
+        }))?;
+        Ok(studio)
+    }
+
+    /// Submits `studio`, adding the fields [`Studio`] has no room for.
+    pub async fn submit(&self, studio: &Studio, login_info: &LoginInfo) -> Result<Value> {
+        let mut body = serde_json::to_value(studio)?;
+        if let Some(topic_id) = self.topic_id {
+            body["topic_id"] = topic_id.into();
+        }
+        post(body, "add", login_info).await
+    }
+}
+
+/// Posts a submission to the client API, `action` is `add` or `edit`.
+pub async fn post(body: Value, action: &str, login_info: &LoginInfo) -> Result<Value> {
+    let ret: Value = reqwest::Client::builder()
+        .user_agent("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 Chrome/63.0.3239.108")
+        .timeout(Duration::from_secs(60))
+        .build()?
+        .post(format!(
+            "http://member.bilibili.com/x/vu/client/{action}?access_key={}",
+            login_info.token_info.access_token
+        ))
+        .json(&body)
+        .send()
+        .await?
+        .json()
+        .await?;
+    info!("{}", ret);
+    if ret["code"] != 0 {
+        bail!("{ret}");
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StudioMeta;
+    use biliup::video::Video;
+
+    fn meta() -> StudioMeta {
+        StudioMeta {
+            title: "title".to_string(),
+            tid: 171,
+            tag: "a, b".to_string(),
+            copyright: 1,
+            part_titles: vec!["P1".to_string()],
+            topic_id: Some(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn defaults() -> anyhow::Result<()> {
+        let default = StudioMeta::default();
+        assert_eq!((default.tid, default.copyright), (171, 1));
+        let parsed: StudioMeta = serde_json::from_str(r#"{"title": "title"}"#)?;
+        assert_eq!((parsed.tid, parsed.copyright), (171, 1));
+        assert!(StudioMeta {
+            title: "title".to_string(),
+            tag: "tag".to_string(),
+            ..default
+        }
+        .validate(1)
+        .is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn validate() {
+        assert!(meta().validate(2).is_ok());
+        assert!(meta().validate(0).is_err());
+        let invalid = [
+            StudioMeta {
+                title: " ".to_string(),
+                ..meta()
+            },
+            StudioMeta {
+                copyright: 2,
+                ..meta()
+            },
+            StudioMeta {
+                tag: "a,,b".to_string(),
+                ..meta()
+            },
+            StudioMeta {
+                dtime: Some(1),
+                ..meta()
+            },
+            StudioMeta {
+                cover: "/nonexistent.jpg".to_string(),
+                ..meta()
+            },
+        ];
+        for meta in invalid {
+            assert!(meta.validate(2).is_err(), "{meta:?}");
+        }
+    }
+
+    #[test]
+    fn to_studio() -> anyhow::Result<()> {
+        let studio = meta().to_studio(vec![Video::new("n1"), Video::new("n2")])?;
+        assert_eq!(studio.videos[0].title.as_deref(), Some("P1"));
+        assert_eq!(studio.videos[1].title, None);
+        assert_eq!(studio.no_reprint, Some(0));
+        Ok(())
+    }
+}
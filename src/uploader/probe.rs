@@ -0,0 +1,219 @@
+use crate::uploader::UploadLine;
+use pyo3::pyclass;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Lines tried in this order when probing gave no usable result.
+const DEFAULT_ORDER: [UploadLine; 5] = [
+    UploadLine::Bda2,
+    UploadLine::Ws,
+    UploadLine::Qn,
+    UploadLine::Kodo,
+    UploadLine::Cos,
+];
+
+/// Bytes posted to a line to estimate its throughput, the same amount `biliup` probes with.
+const PROBE_BYTES: usize = 100 * 1024;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Ranking of the lines from the last probe, shared by all uploads of the process.
+pub static LINE_CACHE: LineCache = LineCache::new();
+
+/// Measurement of one upload line.
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct LineProbe {
+    #[pyo3(get)]
+    pub line: UploadLine,
+    /// Round trip of a GET to the probe URL.
+    #[pyo3(get)]
+    pub latency_ms: Option<f64>,
+    /// Bytes per second of a small POST to the probe URL.
+    #[pyo3(get)]
+    pub throughput: Option<f64>,
+    /// Why the line could not be measured.
+    #[pyo3(get)]
+    pub error: Option<String>,
+}
+
+/// Measures every line concurrently.
+pub async fn probe_lines() -> Vec<LineProbe> {
+    let client = reqwest::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .build()
+        .expect("probe client");
+    let probes = UploadLine::ALL.map(|line| {
+        let client = &client;
+        async move {
+            match probe(client, line).await {
+                Ok((latency, throughput)) => {
+                    info!(
+                        ?line,
+                        latency_ms = latency,
+                        throughput,
+                        "Probed upload line"
+                    );
+                    LineProbe {
+                        line,
+                        latency_ms: Some(latency),
+                        throughput: Some(throughput),
+                        error: None,
+                    }
+                }
+                Err(e) => LineProbe {
+                    line,
+                    latency_ms: None,
+                    throughput: None,
+                    error: Some(e.to_string()),
+                },
+            }
+        }
+    });
+    futures::future::join_all(probes).await
+}
+
+async fn probe(client: &reqwest::Client, line: UploadLine) -> anyhow::Result<(f64, f64)> {
+    let probe_url = serde_json::to_value(line.to_line())?["probe_url"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    if !probe_url.starts_with("//") {
+        anyhow::bail!("line has no probe url");
+    }
+    let url = format!("https:{probe_url}");
+    let instant = Instant::now();
+    client.get(&url).send().await?.error_for_status()?;
+    let latency = instant.elapsed().as_secs_f64() * 1000.;
+    let instant = Instant::now();
+    client
+        .post(&url)
+        .body(vec![0; PROBE_BYTES])
+        .send()
+        .await?
+        .error_for_status()?;
+    let throughput = PROBE_BYTES as f64 / instant.elapsed().as_secs_f64();
+    Ok((latency, throughput))
+}
+
+/// Orders the measured lines by throughput, then latency. Failed lines are left out.
+pub fn rank(probes: &[LineProbe]) -> Vec<UploadLine> {
+    let mut measured: Vec<_> = probes
+        .iter()
+        .filter_map(|p| Some((p.line, p.throughput?, p.latency_ms?)))
+        .collect();
+    measured.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.2.total_cmp(&b.2)));
+    measured.into_iter().map(|(line, _, _)| line).collect()
+}
+
+pub struct LineCache {
+    state: Mutex<CacheState>,
+}
+
+struct CacheState {
+    ttl: Duration,
+    ranked: Option<(Instant, Vec<UploadLine>)>,
+}
+
+impl LineCache {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(CacheState {
+                ttl: Duration::from_secs(30 * 60),
+                ranked: None,
+            }),
+        }
+    }
+
+    pub fn set_ttl(&self, ttl: Duration) {
+        self.state.lock().unwrap().ttl = ttl;
+    }
+
+    pub fn put(&self, probes: &[LineProbe]) {
+        let ranked = rank(probes);
+        self.state.lock().unwrap().ranked = Some((Instant::now(), ranked));
+    }
+
+    /// Cached ranking if it is younger than the TTL.
+    pub fn get(&self) -> Option<Vec<UploadLine>> {
+        let state = self.state.lock().unwrap();
+        match &state.ranked {
+            Some((at, ranked)) if at.elapsed() < state.ttl && !ranked.is_empty() => {
+                Some(ranked.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Moves a line that failed an upload to the end of the cached ranking.
+    pub fn demote(&self, line: UploadLine) {
+        if let Some((_, ranked)) = &mut self.state.lock().unwrap().ranked {
+            if let Some(i) = ranked.iter().position(|&l| l == line) {
+                let line = ranked.remove(i);
+                ranked.push(line);
+            }
+        }
+    }
+
+    /// Lines from best to worst, probing them if the cache is stale.
+    pub async fn ranked(&self) -> Vec<UploadLine> {
+        if let Some(ranked) = self.get() {
+            return ranked;
+        }
+        let probes = probe_lines().await;
+        self.put(&probes);
+        match self.get() {
+            Some(ranked) => ranked,
+            None => {
+                warn!("No upload line could be probed, using the default order");
+                DEFAULT_ORDER.to_vec()
+            }
+        }
+    }
+}
+
+impl Default for LineCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LineCache, LineProbe};
+    use crate::uploader::UploadLine;
+    use std::time::Duration;
+
+    fn probe(line: UploadLine, latency: f64, throughput: Option<f64>) -> LineProbe {
+        LineProbe {
+            line,
+            latency_ms: Some(latency),
+            throughput,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn cache() {
+        let cache = LineCache::new();
+        assert_eq!(cache.get(), None);
+        cache.put(&[
+            probe(UploadLine::Ws, 30., Some(1e6)),
+            probe(UploadLine::Bda2, 20., Some(2e6)),
+            probe(UploadLine::Qn, 10., Some(1e6)),
+            probe(UploadLine::Kodo, 10., None),
+        ]);
+        assert_eq!(
+            cache.get(),
+            Some(vec![UploadLine::Bda2, UploadLine::Qn, UploadLine::Ws])
+        );
+        cache.demote(UploadLine::Bda2);
+        assert_eq!(
+            cache.get(),
+            Some(vec![UploadLine::Qn, UploadLine::Ws, UploadLine::Bda2])
+        );
+        cache.set_ttl(Duration::ZERO);
+        assert_eq!(cache.get(), None);
+    }
+}
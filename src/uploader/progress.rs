@@ -0,0 +1,157 @@
+use pyo3::pyclass;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub type OnProgress = Box<dyn Fn(&str, u64, u64) + Send + Sync>;
+pub type OnComplete = Box<dyn Fn(&Summary) + Send + Sync>;
+
+/// Callbacks notified while files are uploaded.
+///
+/// `on_progress` receives the file name, the bytes uploaded so far and the file size. It fires
+/// at most once per `interval` per file, except for the final update which is always sent.
+pub struct Progress {
+    pub interval: Duration,
+    pub on_progress: Option<OnProgress>,
+    pub on_complete: Option<OnComplete>,
+}
+
+/// Outcome of one uploaded file.
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct Summary {
+    #[pyo3(get)]
+    pub file_name: String,
+    #[pyo3(get)]
+    pub total_bytes: u64,
+    /// Bytes sent by this call, less than `total_bytes` when an upload was resumed.
+    #[pyo3(get)]
+    pub uploaded_bytes: u64,
+    #[pyo3(get)]
+    pub elapsed_secs: f64,
+    /// Bytes per second over `uploaded_bytes`.
+    #[pyo3(get)]
+    pub throughput: f64,
+    /// Name of the video on the server, used in the submission.
+    #[pyo3(get)]
+    pub server_file_name: String,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            on_progress: None,
+            on_complete: None,
+        }
+    }
+}
+
+impl Progress {
+    pub fn file(&self, file_name: &str, total: u64) -> FileProgress<'_> {
+        FileProgress {
+            progress: self,
+            file_name: file_name.to_string(),
+            total: AtomicU64::new(total),
+            started: Instant::now(),
+            uploaded: AtomicU64::new(0),
+            resumed: AtomicU64::new(0),
+            last_report: Mutex::new(None),
+        }
+    }
+}
+
+/// Progress of a single file, shared by the concurrently uploaded chunks.
+pub struct FileProgress<'a> {
+    progress: &'a Progress,
+    file_name: String,
+    total: AtomicU64,
+    started: Instant,
+    uploaded: AtomicU64,
+    resumed: AtomicU64,
+    last_report: Mutex<Option<Instant>>,
+}
+
+impl FileProgress<'_> {
+    /// Adds the bytes of a finished chunk.
+    pub fn add(&self, bytes: u64) {
+        let uploaded = self.uploaded.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.report(uploaded);
+    }
+
+    /// Sets the bytes uploaded by an earlier, interrupted call.
+    pub fn resume(&self, bytes: u64) {
+        self.uploaded.store(bytes, Ordering::Relaxed);
+        self.resumed.store(bytes, Ordering::Relaxed);
+        self.report(bytes);
+    }
+
+    /// Updates the size of a file that was still growing when the upload started.
+    pub fn set_total(&self, total: u64) {
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    fn report(&self, uploaded: u64) {
+        let on_progress = match &self.progress.on_progress {
+            Some(on_progress) => on_progress,
+            None => return,
+        };
+        let total = self.total.load(Ordering::Relaxed);
+        {
+            let mut last_report = self.last_report.lock().unwrap();
+            match *last_report {
+                Some(last) if uploaded < total && last.elapsed() < self.progress.interval => return,
+                _ => *last_report = Some(Instant::now()),
+            }
+        }
+        on_progress(&self.file_name, uploaded, total)
+    }
+
+    /// Reports the summary to `on_complete` and returns it.
+    pub fn finish(self, server_file_name: &str) -> Summary {
+        let elapsed_secs = self.started.elapsed().as_secs_f64();
+        let total = self.total.load(Ordering::Relaxed);
+        let uploaded_bytes = total - self.resumed.load(Ordering::Relaxed).min(total);
+        let summary = Summary {
+            file_name: self.file_name,
+            total_bytes: total,
+            uploaded_bytes,
+            elapsed_secs,
+            throughput: uploaded_bytes as f64 / elapsed_secs.max(f64::EPSILON),
+            server_file_name: server_file_name.to_string(),
+        };
+        if let Some(on_complete) = &self.progress.on_complete {
+            on_complete(&summary);
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Progress;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn rate_limited() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let progress = Progress {
+            interval: Duration::from_secs(3600),
+            on_progress: Some(Box::new({
+                let reports = reports.clone();
+                move |_: &str, uploaded, total| reports.lock().unwrap().push((uploaded, total))
+            })),
+            on_complete: None,
+        };
+        let file = progress.file("a.flv", 30);
+        file.resume(10);
+        file.add(10);
+        file.add(10);
+        // The first and the final update get through.
+        assert_eq!(*reports.lock().unwrap(), [(10, 30), (30, 30)]);
+        let summary = file.finish("n1");
+        assert_eq!(summary.uploaded_bytes, 20);
+        assert_eq!(summary.server_file_name, "n1");
+    }
+}
@@ -0,0 +1,527 @@
+use crate::exceptions::to_py_err;
+use crate::uploader::meta::StudioMeta;
+use crate::uploader::progress::Progress;
+use crate::uploader::retry::RetryPolicy;
+use crate::uploader::{upload, UploadLine};
+use anyhow::{bail, Context, Result};
+use fs2::FileExt;
+use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, info_span, warn, Instrument};
+
+/// How often idle workers look for jobs enqueued by other processes.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+const JOB_EXT: &str = ".job.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+/// A submission waiting in the queue, stored as `<id>.job.json` in the queue directory.
+#[pyclass]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    #[pyo3(get)]
+    pub id: String,
+    #[pyo3(get)]
+    pub video_path: Vec<PathBuf>,
+    #[pyo3(get)]
+    pub cookie_file: PathBuf,
+    #[pyo3(get)]
+    pub meta: StudioMeta,
+    #[pyo3(get)]
+    pub line: Option<UploadLine>,
+    #[pyo3(get)]
+    pub limit: usize,
+    pub status: JobStatus,
+    /// Attempts made so far, including the running one.
+    #[pyo3(get)]
+    pub attempts: u32,
+    /// Error of the last failed attempt.
+    #[pyo3(get)]
+    pub error: Option<String>,
+    /// Server response to the submission as JSON.
+    #[pyo3(get)]
+    pub response: Option<String>,
+    /// Unix timestamps in seconds.
+    #[pyo3(get)]
+    pub created: u64,
+    #[pyo3(get)]
+    pub updated: u64,
+    /// A failed job is not picked up again before this time.
+    #[pyo3(get)]
+    pub retry_at: u64,
+}
+
+#[pymethods]
+impl Job {
+    /// One of `queued`, `running`, `done` or `failed`.
+    #[getter]
+    fn status(&self) -> &'static str {
+        match self.status {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Job(id={:?}, status={:?}, attempts={}, title={:?})",
+            self.id,
+            self.status(),
+            self.attempts,
+            self.meta.title
+        )
+    }
+}
+
+/// Jobs persisted in a directory and uploaded by a pool of workers with [`upload`].
+///
+/// Any process may enqueue jobs and query their status, but only the one holding
+/// [`UploadQueue::acquire`] runs them. Jobs that were running when that process died are
+/// queued again by the next one, and resume from their upload session.
+pub struct UploadQueue {
+    dir: PathBuf,
+    /// Keeps the workers of this process from claiming the same job.
+    claim: Mutex<()>,
+}
+
+impl UploadQueue {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create queue directory {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            claim: Mutex::new(()),
+        })
+    }
+
+    fn job_file(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}{JOB_EXT}"))
+    }
+
+    fn session_file(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.session.json"))
+    }
+
+    fn save(&self, job: &Job) -> Result<()> {
+        let path = self.job_file(&job.id);
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(job)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Millisecond part of the next job id. Ids sort in enqueue order even within a
+    /// millisecond, across all processes enqueueing: the last one is kept in `last.id`, locked
+    /// while it is read and replaced. `queue.lock` can not be used, the worker holds it.
+    fn next_id(&self, now: Duration) -> Result<u64> {
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.dir.join("last.id"))?;
+        file.lock_exclusive()?;
+        let mut last = String::new();
+        file.read_to_string(&mut last)?;
+        let next = last
+            .trim()
+            .parse::<u64>()
+            .unwrap_or_default()
+            .max(now.as_millis() as u64 - 1)
+            + 1;
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(next.to_string().as_bytes())?;
+        Ok(next)
+    }
+
+    /// Validates `meta` and adds a job uploading `video_path` to the end of the queue.
+    pub fn enqueue(
+        &self,
+        video_path: Vec<PathBuf>,
+        cookie_file: &Path,
+        meta: StudioMeta,
+        line: Option<UploadLine>,
+        limit: usize,
+    ) -> Result<Job> {
+        meta.validate(video_path.len())?;
+        // The worker may run in another directory.
+        let canonicalize = |path: &Path| {
+            path.canonicalize()
+                .with_context(|| format!("{} not found", path.display()))
+        };
+        let video_path = video_path
+            .iter()
+            .map(|path| canonicalize(path))
+            .collect::<Result<_>>()?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let job = Job {
+            id: format!("{:013}-{:04x}", self.next_id(now)?, rand::random::<u16>()),
+            video_path,
+            cookie_file: canonicalize(cookie_file)?,
+            meta,
+            line,
+            limit,
+            status: JobStatus::Queued,
+            attempts: 0,
+            error: None,
+            response: None,
+            created: now.as_secs(),
+            updated: now.as_secs(),
+            retry_at: 0,
+        };
+        self.save(&job)?;
+        info!(id = %job.id, title = %job.meta.title, "Enqueued upload");
+        Ok(job)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<Job>> {
+        match std::fs::read(self.job_file(id)) {
+            Ok(json) => Ok(Some(serde_json::from_slice(&json)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// All jobs, oldest first.
+    pub fn jobs(&self) -> Result<Vec<Job>> {
+        let mut jobs = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if !path.to_string_lossy().ends_with(JOB_EXT) {
+                continue;
+            }
+            match std::fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| Ok(serde_json::from_slice::<Job>(&json)?))
+            {
+                Ok(job) => jobs.push(job),
+                Err(e) => warn!("Skipping unreadable job {}: {e}", path.display()),
+            }
+        }
+        jobs.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(jobs)
+    }
+
+    /// Queues a failed job again with a fresh set of attempts.
+    pub fn retry(&self, id: &str) -> Result<()> {
+        let mut job = self.get(id)?.with_context(|| format!("no job {id}"))?;
+        if job.status != JobStatus::Failed {
+            bail!("job {id} has not failed");
+        }
+        job.status = JobStatus::Queued;
+        job.attempts = 0;
+        job.retry_at = 0;
+        self.save(&job)
+    }
+
+    /// Deletes a job that is not running, along with its upload session.
+    pub fn remove(&self, id: &str) -> Result<()> {
+        let job = self.get(id)?.with_context(|| format!("no job {id}"))?;
+        if job.status == JobStatus::Running {
+            bail!("job {id} is running");
+        }
+        std::fs::remove_file(self.job_file(id))?;
+        match std::fs::remove_file(self.session_file(id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Locks the queue for this process until the returned file is dropped, and queues the
+    /// jobs a previous worker left running.
+    pub fn acquire(&self) -> Result<File> {
+        let lock = File::create(self.dir.join("queue.lock"))?;
+        lock.try_lock_exclusive().with_context(|| {
+            format!(
+                "{} is already being processed by another worker",
+                self.dir.display()
+            )
+        })?;
+        self.requeue_running()?;
+        Ok(lock)
+    }
+
+    /// Puts interrupted jobs back into the queue, the interrupted attempt does not count.
+    pub fn requeue_running(&self) -> Result<()> {
+        for mut job in self.jobs()? {
+            if job.status == JobStatus::Running {
+                info!(id = %job.id, "Requeueing interrupted upload");
+                job.status = JobStatus::Queued;
+                job.attempts = job.attempts.saturating_sub(1);
+                self.save(&job)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks the oldest job that is due as running.
+    fn claim(&self) -> Result<Option<Job>> {
+        let _claim = self.claim.lock().unwrap();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let job = self
+            .jobs()?
+            .into_iter()
+            .find(|job| job.status == JobStatus::Queued && job.retry_at <= now);
+        match job {
+            Some(mut job) => {
+                job.status = JobStatus::Running;
+                job.attempts += 1;
+                job.updated = now;
+                self.save(&job)?;
+                Ok(Some(job))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Uploads the queued jobs with `workers` at a time, forever. Needs [`UploadQueue::acquire`].
+    ///
+    /// A failed job is retried after a backoff until it used up `retry.max_attempts`.
+    pub async fn run(&self, workers: usize, retry: &RetryPolicy) {
+        info!(dir = %self.dir.display(), workers, "Upload queue started");
+        let workers = (0..workers.max(1)).map(|_| async {
+            loop {
+                match self.claim() {
+                    Ok(Some(job)) => self.process(job, retry).await,
+                    Ok(None) => tokio::time::sleep(POLL_INTERVAL).await,
+                    Err(e) => {
+                        warn!("Failed to read the upload queue: {e:#}");
+                        tokio::time::sleep(POLL_INTERVAL).await
+                    }
+                }
+            }
+        });
+        futures::future::join_all(workers).await;
+    }
+
+    async fn process(&self, mut job: Job, retry: &RetryPolicy) {
+        let span = info_span!("job", id = %job.id, attempt = job.attempts);
+        let result = upload(
+            job.video_path.clone(),
+            job.cookie_file.clone(),
+            job.line,
+            job.limit,
+            job.meta.clone(),
+            Some(self.session_file(&job.id)),
+            RetryPolicy::default(),
+            Progress::default(),
+        )
+        .instrument(span.clone())
+        .await;
+        let _enter = span.enter();
+        job.updated = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match result {
+            Ok(res) => {
+                info!("Queued upload submitted");
+                job.status = JobStatus::Done;
+                job.error = None;
+                job.response = Some(res.to_string());
+            }
+            Err(e) => {
+                job.error = Some(format!("{e:#}"));
+                if job.attempts < retry.max_attempts {
+                    let backoff = retry.backoff(job.attempts);
+                    warn!(
+                        backoff_secs = backoff.as_secs(),
+                        "Queued upload failed: {e:#}"
+                    );
+                    job.status = JobStatus::Queued;
+                    job.retry_at = job.updated + backoff.as_secs();
+                } else {
+                    warn!("Queued upload failed for good: {e:#}");
+                    job.status = JobStatus::Failed;
+                }
+            }
+        }
+        if let Err(e) = self.save(&job) {
+            warn!("Failed to save job: {e:#}");
+        }
+    }
+}
+
+/// Retries of whole jobs, much slower than the ones of single chunks.
+pub fn job_retry_policy(max_attempts: u32) -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: max_attempts.max(1),
+        initial_backoff: Duration::from_secs(60),
+        max_backoff: Duration::from_secs(60 * 60),
+    }
+}
+
+struct Daemon {
+    /// Dropping it stops the workers.
+    stop: tokio::sync::oneshot::Sender<()>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+/// [`UploadQueue`] for Python, `start` runs the workers on a background thread.
+#[pyclass(name = "UploadQueue")]
+pub struct PyUploadQueue {
+    queue: Arc<UploadQueue>,
+    daemon: Option<Daemon>,
+}
+
+// pyo3 0.16 nests the `#[new]` wrapper in a static, outside the scope of an `allow` on the
+// impl itself.
+#[allow(non_local_definitions)]
+const _: () = {
+    #[pymethods]
+    impl PyUploadQueue {
+        #[new]
+        fn new(dir: PathBuf) -> PyResult<Self> {
+            Ok(Self {
+                queue: Arc::new(UploadQueue::open(dir).map_err(to_py_err)?),
+                daemon: None,
+            })
+        }
+
+        /// Adds a job and returns its id, `meta` is a `StudioMeta` or a dict of its keyword
+        /// arguments.
+        #[args(line = "None", limit = "3")]
+        fn enqueue(
+            &self,
+            video_path: Vec<PathBuf>,
+            cookie_file: PathBuf,
+            meta: &PyAny,
+            line: Option<UploadLine>,
+            limit: usize,
+        ) -> PyResult<String> {
+            let meta = StudioMeta::from_py(meta)?;
+            meta.validate(video_path.len())
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+            self.queue
+                .enqueue(video_path, &cookie_file, meta, line, limit)
+                .map(|job| job.id)
+                .map_err(to_py_err)
+        }
+
+        fn status(&self, id: &str) -> PyResult<Option<Job>> {
+            self.queue.get(id).map_err(to_py_err)
+        }
+
+        fn jobs(&self) -> PyResult<Vec<Job>> {
+            self.queue.jobs().map_err(to_py_err)
+        }
+
+        fn retry(&self, id: &str) -> PyResult<()> {
+            self.queue.retry(id).map_err(to_py_err)
+        }
+
+        fn remove(&self, id: &str) -> PyResult<()> {
+            self.queue.remove(id).map_err(to_py_err)
+        }
+
+        /// Starts `workers` concurrent uploads in the background, each job gets `max_attempts`.
+        #[args(workers = "1", max_attempts = "3")]
+        fn start(&mut self, workers: usize, max_attempts: u32) -> PyResult<()> {
+            if self.daemon.is_some() {
+                return Err(pyo3::exceptions::PyRuntimeError::new_err(
+                    "upload queue already started",
+                ));
+            }
+            let lock = self.queue.acquire().map_err(to_py_err)?;
+            let queue = self.queue.clone();
+            let (stop, stopped) = tokio::sync::oneshot::channel();
+            crate::logging::init();
+            let thread = std::thread::spawn(move || {
+                let retry = job_retry_policy(max_attempts);
+                crate::runtime::block_on(async {
+                    tokio::select! {
+                        _ = queue.run(workers, &retry) => {}
+                        _ = stopped => {}
+                    }
+                });
+                if let Err(e) = queue.requeue_running() {
+                    warn!("Failed to requeue interrupted uploads: {e:#}");
+                }
+                drop(lock);
+            });
+            self.daemon = Some(Daemon { stop, thread });
+            Ok(())
+        }
+
+        #[getter]
+        fn running(&self) -> bool {
+            self.daemon.is_some()
+        }
+
+        /// Stops the workers, running jobs are interrupted and resume on the next `start`.
+        fn stop(&mut self, py: Python<'_>) -> PyResult<()> {
+            if let Some(Daemon { stop, thread }) = self.daemon.take() {
+                let _ = stop.send(());
+                py.allow_threads(|| thread.join()).map_err(|_| {
+                    pyo3::exceptions::PyRuntimeError::new_err("upload queue worker panicked")
+                })?;
+            }
+            Ok(())
+        }
+    }
+};
+
+#[cfg(test)]
+mod tests {
+    use super::{JobStatus, UploadQueue};
+    use crate::uploader::meta::StudioMeta;
+
+    #[test]
+    fn persists_jobs() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("upload-queue-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let queue = UploadQueue::open(&dir)?;
+        let video = dir.join("a.flv");
+        std::fs::write(&video, b"flv")?;
+        let meta: StudioMeta = serde_json::from_str(r#"{"title": "t", "tag": "a"}"#)?;
+        assert!(queue
+            .enqueue(vec![video.clone()], &video, StudioMeta::default(), None, 3)
+            .is_err());
+        let first = queue.enqueue(vec![video.clone()], &video, meta.clone(), None, 3)?;
+        let second =
+            UploadQueue::open(&dir)?.enqueue(vec![video.clone()], &video, meta, None, 3)?;
+        assert!(first.id < second.id);
+
+        let _lock = queue.acquire()?;
+        assert!(UploadQueue::open(&dir)?.acquire().is_err());
+        let claimed = queue.claim()?.unwrap();
+        assert_eq!(
+            (claimed.id.as_str(), claimed.attempts),
+            (first.id.as_str(), 1)
+        );
+        assert!(queue.remove(&first.id).is_err());
+
+        // Another process sees the same state, an interrupted job is queued again.
+        let reopened = UploadQueue::open(&dir)?;
+        let jobs = reopened.jobs()?;
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].status, JobStatus::Running);
+        assert_eq!(jobs[0].meta.copyright, 1);
+        reopened.requeue_running()?;
+        assert_eq!(reopened.get(&first.id)?.unwrap().status, JobStatus::Queued);
+        assert!(reopened.retry(&first.id).is_err());
+
+        reopened.remove(&second.id)?;
+        assert!(reopened.get(&second.id)?.is_none());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use rand::Rng;
+use reqwest::StatusCode;
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+
+/// Exponential backoff with jitter for requests to an upload line.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Attempts per request including the first one.
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled on every further one.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(64),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait after the `attempt`th failed attempt, counting from 1.
+    ///
+    /// Up to half of the exponential delay is randomized so that concurrent chunks failing
+    /// together do not hit the line again at the same moment.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.max_backoff);
+        let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+        exponential.mul_f64(jitter)
+    }
+
+    /// Runs `f` until it succeeds, fails with a permanent error or runs out of attempts.
+    pub async fn retry<F, Fut, T>(&self, mut f: F) -> reqwest::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = reqwest::Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match f().await {
+                Err(e) if attempt < self.max_attempts && is_transient(&e) => {
+                    let backoff = self.backoff(attempt);
+                    warn!(
+                        attempt,
+                        max_attempts = self.max_attempts,
+                        backoff_ms = backoff.as_millis() as u64,
+                        status = e.status().map(|s| s.as_u16()),
+                        error = %e,
+                        "Request to upload line failed, retrying"
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+/// Connection errors, timeouts, 5xx and 429 are worth another try, other statuses are not.
+fn is_transient(e: &reqwest::Error) -> bool {
+    match e.status() {
+        Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+        };
+        let first = policy.backoff(1);
+        assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+        let third = policy.backoff(3);
+        assert!(third >= Duration::from_secs(2) && third <= Duration::from_secs(4));
+        assert!(policy.backoff(40) <= Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::uploader::upos::Bucket;
+use anyhow::Result;
+use biliup::video::Video;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Progress of a multi-video upload, persisted so that a failed upload can be resumed.
+///
+/// The state file is rewritten after every finished chunk and removed once the submission
+/// succeeded. Without a state file the session only lives in memory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Session {
+    /// Videos already uploaded, in upload order.
+    pub completed: Vec<Completed>,
+    /// The upos upload that was interrupted, if any.
+    pub in_flight: Option<InFlight>,
+    #[serde(skip)]
+    state_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Completed {
+    pub path: PathBuf,
+    pub size: u64,
+    pub video: Video,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InFlight {
+    pub path: PathBuf,
+    pub size: u64,
+    pub bucket: Bucket,
+    pub upload_id: String,
+    /// Indexes of the chunks already accepted by the line.
+    pub parts: Vec<usize>,
+    /// ETags the line returned for the chunks, by index. Missing in older state files.
+    #[serde(default)]
+    pub etags: BTreeMap<usize, String>,
+}
+
+impl Session {
+    /// Loads the session from `state_file`, or starts a new one if it does not exist yet.
+    pub fn load(state_file: &Path) -> Result<Self> {
+        let mut session: Session = match std::fs::read(state_file) {
+            Ok(json) => {
+                info!("Resuming upload session {}", state_file.display());
+                serde_json::from_slice(&json)?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(e) => return Err(e.into()),
+        };
+        session.state_file = Some(state_file.to_path_buf());
+        Ok(session)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(state_file) = &self.state_file {
+            let mut tmp = state_file.as_os_str().to_owned();
+            tmp.push(".tmp");
+            std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+            std::fs::rename(&tmp, state_file)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the video uploaded earlier from `path`, unless the file changed since.
+    pub fn completed(&self, path: &Path, size: u64) -> Option<Video> {
+        self.completed
+            .iter()
+            .find(|c| c.path == path && c.size == size)
+            .map(|c| Video {
+                title: c.video.title.clone(),
+                filename: c.video.filename.clone(),
+                desc: c.video.desc.clone(),
+            })
+    }
+
+    /// Returns the interrupted upload of `path`, unless the file changed since.
+    pub fn in_flight(&self, path: &Path, size: u64) -> Option<&InFlight> {
+        self.in_flight
+            .as_ref()
+            .filter(|f| f.path == path && f.size == size)
+    }
+
+    /// Forgets the interrupted upload, e.g. before starting over on another line.
+    pub fn discard_in_flight(&mut self) -> Result<()> {
+        if self.in_flight.take().is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn part_done(&mut self, chunk: usize, etag: String) -> Result<()> {
+        if let Some(in_flight) = &mut self.in_flight {
+            in_flight.parts.push(chunk);
+            in_flight.etags.insert(chunk, etag);
+        }
+        self.save()
+    }
+
+    pub fn complete(&mut self, path: PathBuf, size: u64, video: &Video) -> Result<()> {
+        self.in_flight = None;
+        self.completed.push(Completed {
+            path,
+            size,
+            video: Video {
+                title: video.title.clone(),
+                filename: video.filename.clone(),
+                desc: video.desc.clone(),
+            },
+        });
+        self.save()
+    }
+
+    /// Removes the state file once the submission went through.
+    pub fn finish(self) -> Result<()> {
+        match self.state_file.map(std::fs::remove_file) {
+            Some(Err(e)) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Session;
+    use biliup::video::Video;
+    use std::path::Path;
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        let state_file =
+            std::env::temp_dir().join(format!("stream-gears-session-{}.json", std::process::id()));
+        let mut session = Session::load(&state_file)?;
+        session.complete("a.flv".into(), 10, &Video::new("n1"))?;
+
+        let session = Session::load(&state_file)?;
+        assert_eq!(
+            session
+                .completed(Path::new("a.flv"), 10)
+                .map(|v| v.filename),
+            Some("n1".to_string())
+        );
+        assert!(session.completed(Path::new("a.flv"), 11).is_none());
+        session.finish()?;
+        assert!(!state_file.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,586 @@
+use crate::uploader::limiter::UPLOAD_LIMITER;
+use crate::uploader::progress::FileProgress;
+use crate::uploader::retry::RetryPolicy;
+use crate::uploader::session::{InFlight, Session};
+use anyhow::{bail, Result};
+use biliup::client::Client;
+use biliup::line::Line;
+use biliup::video::Video;
+use biliup::VideoFile;
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt, TryStreamExt};
+use reqwest::header::{self, CONTENT_LENGTH, ETAG};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, info_span, warn, Instrument};
+
+pub(crate) const TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Sent for chunks without a known ETag: lines that return none, or chunks recorded by state
+/// files of older versions.
+const UNKNOWN_ETAG: &str = "etag";
+
+/// Upload target handed out by `preupload` for upos lines.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bucket {
+    pub chunk_size: usize,
+    pub auth: String,
+    pub endpoint: String,
+    pub biz_id: usize,
+    pub upos_uri: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Protocol<'a> {
+    upload_id: &'a str,
+    chunks: usize,
+    total: u64,
+    chunk: usize,
+    size: usize,
+    part_number: usize,
+    start: u64,
+    end: u64,
+}
+
+/// Whether chunks of an interrupted upload to `line` can be resumed, which only upos supports.
+pub fn resumable(line: &Line) -> bool {
+    serde_json::to_value(line).is_ok_and(|line| line["os"] == "upos")
+}
+
+/// Uploads `path` through an upos line, resuming the session's in-flight upload when it
+/// belongs to the same file.
+pub async fn upload(
+    client: &Client,
+    line: &Line,
+    path: &Path,
+    limit: usize,
+    retry: &RetryPolicy,
+    progress: &FileProgress<'_>,
+    session: &mut Session,
+) -> Result<Video> {
+    let size = std::fs::metadata(path)?.len();
+    if let Some(in_flight) = session.in_flight(path, size) {
+        info!(
+            "Resuming {} at chunk {}/{}",
+            path.display(),
+            in_flight.parts.len(),
+            chunks(size, in_flight.bucket.chunk_size)
+        );
+        match upload_in_flight(limit, retry, progress, session).await {
+            Ok(video) => return Ok(video),
+            // The upload id may have expired in the meantime.
+            Err(e) => warn!("Unable to resume {}, starting over: {e}", path.display()),
+        }
+    }
+    let bucket: Bucket = line
+        .to_uploader(VideoFile::new(path)?)
+        .pre_upload(client)
+        .await?;
+    let upload_id = Upos::new(&bucket, retry)?.upload_id().await?;
+    session.in_flight = Some(InFlight {
+        path: path.to_path_buf(),
+        size,
+        bucket,
+        upload_id,
+        parts: Vec::new(),
+        etags: Default::default(),
+    });
+    session.save()?;
+    upload_in_flight(limit, retry, progress, session).await
+}
+
+/// Uploads a recording through an upos line while it is still being written.
+///
+/// `size_hint` is announced to the line in place of the unknown final size. The upload is
+/// completed once `source` ends, which is how the writer signals that it is done.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_live(
+    client: &Client,
+    line: &Line,
+    file_name: &str,
+    size_hint: u64,
+    source: impl Stream<Item = std::io::Result<Bytes>>,
+    limit: usize,
+    retry: &RetryPolicy,
+    progress: &FileProgress<'_>,
+) -> Result<Video> {
+    let query = serde_json::to_value(line)?["query"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let bucket: Bucket = client
+        .client
+        .get(format!("https://member.bilibili.com/preupload?{query}"))
+        .query(&json!({
+            "r": "upos",
+            "profile": "ugcupos/bup",
+            "ssl": 0,
+            "version": "2.11.0",
+            "build": 2110000,
+            "name": file_name,
+            "size": size_hint,
+        }))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let mut upos = Upos::new(&bucket, retry)?;
+    upos.upload_id = upos.upload_id().await?;
+    upos.path = PathBuf::from(file_name);
+    let limit = UPLOAD_LIMITER.concurrency(limit, bucket.chunk_size, TIMEOUT);
+    let parts = upos
+        .upload_stream(source, size_hint, limit, progress)
+        .await?;
+    info!(
+        "Live upload of {file_name} finished with {} chunks",
+        parts.len()
+    );
+    upos.complete_parts(parts).await
+}
+
+async fn upload_in_flight(
+    limit: usize,
+    retry: &RetryPolicy,
+    progress: &FileProgress<'_>,
+    session: &mut Session,
+) -> Result<Video> {
+    let in_flight = session.in_flight.as_ref().expect("in-flight upload");
+    let upos = Upos::from(in_flight, retry)?;
+    let (done, pending): (Vec<usize>, Vec<usize>) = (0..chunks(upos.size, upos.bucket.chunk_size))
+        .partition(|chunk| in_flight.parts.contains(chunk));
+    progress.resume(done.iter().map(|&chunk| upos.chunk_len(chunk)).sum());
+    let limit = UPLOAD_LIMITER.concurrency(limit, upos.bucket.chunk_size, TIMEOUT);
+    let stream = upos.upload_chunks(pending, limit);
+    tokio::pin!(stream);
+    while let Some((chunk, etag)) = stream.try_next().await? {
+        session.part_done(chunk, etag)?;
+        progress.add(upos.chunk_len(chunk));
+    }
+    let in_flight = session.in_flight.as_ref().expect("in-flight upload");
+    let parts = (0..chunks(upos.size, upos.bucket.chunk_size))
+        .map(|chunk| {
+            let etag = in_flight.etags.get(&chunk).cloned();
+            (chunk, etag.unwrap_or_else(|| UNKNOWN_ETAG.to_string()))
+        })
+        .collect();
+    upos.complete_parts(parts).await
+}
+
+fn chunks(size: u64, chunk_size: usize) -> usize {
+    size.div_ceil(chunk_size as u64) as usize
+}
+
+pub struct Upos {
+    client: reqwest::Client,
+    url: String,
+    bucket: Bucket,
+    upload_id: String,
+    path: PathBuf,
+    size: u64,
+    retry: RetryPolicy,
+}
+
+impl Upos {
+    fn new(bucket: &Bucket, retry: &RetryPolicy) -> Result<Self> {
+        let mut headers = header::HeaderMap::new();
+        headers.insert("X-Upos-Auth", header::HeaderValue::from_str(&bucket.auth)?);
+        let client = reqwest::Client::builder()
+            .user_agent("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 Chrome/63.0.3239.108")
+            .default_headers(headers)
+            .timeout(TIMEOUT)
+            .build()?;
+        Ok(Self {
+            client,
+            url: format!(
+                "{}/{}",
+                https(&bucket.endpoint),
+                bucket.upos_uri.replace("upos://", "")
+            ),
+            bucket: bucket.clone(),
+            upload_id: String::new(),
+            path: PathBuf::new(),
+            size: 0,
+            retry: retry.clone(),
+        })
+    }
+
+    pub fn from(in_flight: &InFlight, retry: &RetryPolicy) -> Result<Self> {
+        Ok(Self {
+            upload_id: in_flight.upload_id.clone(),
+            path: in_flight.path.clone(),
+            size: in_flight.size,
+            ..Self::new(&in_flight.bucket, retry)?
+        })
+    }
+
+    async fn upload_id(&self) -> Result<String> {
+        let res: serde_json::Value = self
+            .client
+            .post(format!("{}?uploads&output=json", self.url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        match res["upload_id"].as_str() {
+            Some(upload_id) => Ok(upload_id.to_string()),
+            None => bail!("no upload id: {res}"),
+        }
+    }
+
+    /// Uploads the given chunks with up to `limit` in flight, yields the index and ETag of
+    /// each finished one.
+    pub fn upload_chunks(
+        &self,
+        chunks: Vec<usize>,
+        limit: usize,
+    ) -> impl Stream<Item = Result<(usize, String)>> + '_ {
+        futures::stream::iter(chunks)
+            .map(move |chunk| self.upload_chunk(chunk))
+            .buffer_unordered(limit)
+    }
+
+    fn chunk_len(&self, chunk: usize) -> u64 {
+        let chunk_size = self.bucket.chunk_size as u64;
+        chunk_size.min(self.size - chunk as u64 * chunk_size)
+    }
+
+    async fn upload_chunk(&self, chunk: usize) -> Result<(usize, String)> {
+        let start = chunk as u64 * self.bucket.chunk_size as u64;
+        let len = self.chunk_len(chunk) as usize;
+        let body = read_chunk(&self.path, start, len)?;
+        let params = Protocol {
+            upload_id: &self.upload_id,
+            chunks: chunks(self.size, self.bucket.chunk_size),
+            total: self.size,
+            chunk,
+            size: len,
+            part_number: chunk + 1,
+            start,
+            end: start + len as u64,
+        };
+        let etag = self.put_chunk(&params, body).await?;
+        Ok((chunk, etag))
+    }
+
+    /// Returns the ETag of the accepted chunk.
+    async fn put_chunk(&self, params: &Protocol<'_>, body: Bytes) -> Result<String> {
+        let res = self
+            .retry
+            .retry(|| async {
+                self.client
+                    .put(&self.url)
+                    .query(params)
+                    .header(CONTENT_LENGTH, params.size)
+                    .body(UPLOAD_LIMITER.body(body.clone()))
+                    .send()
+                    .await?
+                    .error_for_status()
+            })
+            .instrument(info_span!(
+                "chunk",
+                chunk = params.chunk,
+                start = params.start
+            ))
+            .await?;
+        Ok(res
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .unwrap_or(UNKNOWN_ETAG)
+            .to_string())
+    }
+
+    /// Uploads `source` in chunks as its bytes arrive, returns the number and ETag of every
+    /// chunk.
+    ///
+    /// The final size is unknown until `source` ends, so the chunks before the last one report
+    /// the announced `size_hint`, or the size known so far once the recording outgrew it. A
+    /// chunk is held back until the next byte arrives, so that the last one reports the final
+    /// size and chunk count. The line assembles the parts by their number.
+    async fn upload_stream(
+        &self,
+        source: impl Stream<Item = std::io::Result<Bytes>>,
+        size_hint: u64,
+        limit: usize,
+        progress: &FileProgress<'_>,
+    ) -> Result<Vec<(usize, String)>> {
+        let chunk_size = self.bucket.chunk_size;
+        let stream = rechunk(source, chunk_size)
+            .map(|chunk| async move {
+                let (chunk, body, last) = chunk?;
+                let start = chunk as u64 * chunk_size as u64;
+                let end = start + body.len() as u64;
+                let total = match last {
+                    true => end,
+                    false => size_hint.max(end),
+                };
+                progress.set_total(total);
+                let params = Protocol {
+                    upload_id: &self.upload_id,
+                    chunks: chunks(total, chunk_size),
+                    total,
+                    chunk,
+                    size: body.len(),
+                    part_number: chunk + 1,
+                    start,
+                    end,
+                };
+                let etag = self.put_chunk(&params, body).await?;
+                Ok::<_, anyhow::Error>((chunk, etag, params.size as u64))
+            })
+            .buffered(limit);
+        tokio::pin!(stream);
+        let mut parts = Vec::new();
+        while let Some((chunk, etag, len)) = stream.try_next().await? {
+            parts.push((chunk, etag));
+            progress.add(len);
+        }
+        if parts.is_empty() {
+            bail!("the live source ended without any data");
+        }
+        Ok(parts)
+    }
+
+    /// Asks the line to assemble the uploaded `parts`, given by chunk index and ETag.
+    async fn complete_parts(&self, parts: Vec<(usize, String)>) -> Result<Video> {
+        let parts: Vec<_> = parts
+            .into_iter()
+            .map(|(chunk, etag)| json!({"partNumber": chunk + 1, "eTag": etag}))
+            .collect();
+        let res: serde_json::Value = self
+            .client
+            .post(&self.url)
+            .query(&json!({
+                "name": self.path.file_name().and_then(|name| name.to_str()),
+                "uploadId": self.upload_id,
+                "biz_id": self.bucket.biz_id,
+                "output": "json",
+                "profile": "ugcupos/bup"
+            }))
+            .json(&json!({ "parts": parts }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if res["OK"] != 1 {
+            bail!("failed to complete upload: {res}");
+        }
+        let stem = |path: &Path| {
+            path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(str::to_string)
+        };
+        Ok(Video {
+            title: stem(&self.path),
+            filename: stem(Path::new(&self.bucket.upos_uri)).unwrap_or_default(),
+            desc: "".into(),
+        })
+    }
+}
+
+/// Regroups the bytes of `source` into numbered chunks of `chunk_size`, the last one may be
+/// shorter and is flagged as such.
+fn rechunk(
+    source: impl Stream<Item = std::io::Result<Bytes>>,
+    chunk_size: usize,
+) -> impl Stream<Item = std::io::Result<(usize, Bytes, bool)>> {
+    let source = Some(Box::pin(source));
+    futures::stream::unfold(
+        (source, BytesMut::new(), 0),
+        move |(mut source, mut buf, chunk)| async move {
+            loop {
+                // A full chunk is only the last one if nothing follows it.
+                if buf.len() > chunk_size {
+                    let body = buf.split_to(chunk_size).freeze();
+                    return Some((Ok((chunk, body, false)), (source, buf, chunk + 1)));
+                }
+                let Some(stream) = source.as_mut() else {
+                    if buf.is_empty() {
+                        return None;
+                    }
+                    let body = buf.split().freeze();
+                    return Some((Ok((chunk, body, true)), (None, buf, chunk + 1)));
+                };
+                match stream.next().await {
+                    Some(Ok(bytes)) => buf.extend_from_slice(&bytes),
+                    Some(Err(e)) => return Some((Err(e), (None, BytesMut::new(), chunk))),
+                    None => source = None,
+                }
+            }
+        },
+    )
+}
+
+/// Endpoints come as `//host`, a full URL is accepted as well.
+pub(crate) fn https(endpoint: &str) -> String {
+    match endpoint.starts_with("//") {
+        true => format!("https:{endpoint}"),
+        false => endpoint.to_string(),
+    }
+}
+
+pub(crate) fn read_chunk(path: &Path, start: u64, len: usize) -> std::io::Result<Bytes> {
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut buf = vec![0; len];
+    file.read_exact(&mut buf)?;
+    Ok(buf.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{upload_in_flight, Bucket, Upos};
+    use crate::uploader::progress::Progress;
+    use crate::uploader::retry::RetryPolicy;
+    use crate::uploader::session::{InFlight, Session};
+    use futures::StreamExt;
+    use std::time::Duration;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn session(server: &MockServer, file: &std::path::Path) -> Session {
+        let mut session = Session::default();
+        session.in_flight = Some(InFlight {
+            path: file.to_path_buf(),
+            size: 10,
+            bucket: Bucket {
+                chunk_size: 4,
+                auth: "auth".to_string(),
+                endpoint: server.uri(),
+                biz_id: 1,
+                upos_uri: "upos://ugcfx/n1.mp4".to_string(),
+            },
+            upload_id: "id".to_string(),
+            parts: vec![],
+            etags: Default::default(),
+        });
+        session
+    }
+
+    fn retry(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_intermittent_failures() -> anyhow::Result<()> {
+        let file =
+            std::env::temp_dir().join(format!("stream-gears-upos-{}.mp4", std::process::id()));
+        std::fs::write(&file, b"0123456789")?;
+        let server = MockServer::start().await;
+        // The middle chunk fails twice, everything else once.
+        Mock::given(method("PUT"))
+            .and(query_param("partNumber", "2"))
+            .respond_with(ResponseTemplate::new(502))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(3)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/ugcfx/n1.mp4"))
+            .respond_with(ResponseTemplate::new(200).insert_header("ETag", "abc"))
+            .expect(3)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(query_param("uploadId", "id"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"OK": 1})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut session = session(&server, &file);
+        let video = upload_in_flight(
+            2,
+            &retry(5),
+            &Progress::default().file("", 10),
+            &mut session,
+        )
+        .await?;
+        assert_eq!(video.filename, "n1");
+        let in_flight = session.in_flight.unwrap();
+        let mut parts = in_flight.parts;
+        parts.sort_unstable();
+        assert_eq!(parts, [0, 1, 2]);
+        assert!(in_flight.etags.values().all(|etag| etag == "abc"));
+        std::fs::remove_file(file)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gives_up() -> anyhow::Result<()> {
+        let file =
+            std::env::temp_dir().join(format!("stream-gears-upos-fail-{}.mp4", std::process::id()));
+        std::fs::write(&file, b"0123456789")?;
+        // Server errors are retried until the attempts run out, client errors are not.
+        for (status, attempts) in [(500, 3), (403, 1)] {
+            let server = MockServer::start().await;
+            Mock::given(method("PUT"))
+                .and(query_param("partNumber", "1"))
+                .respond_with(ResponseTemplate::new(status))
+                .expect(attempts)
+                .mount(&server)
+                .await;
+            let mut session = session(&server, &file);
+            assert!(upload_in_flight(
+                1,
+                &retry(3),
+                &Progress::default().file("", 10),
+                &mut session
+            )
+            .await
+            .is_err());
+        }
+        std::fs::remove_file(file)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn uploads_stream() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        // Only the last chunk knows the final size, the others report the announced one.
+        for (part, total, chunks, etag) in [
+            ("1", "16", "4", "e1"),
+            ("2", "16", "4", "e2"),
+            ("3", "10", "3", "e3"),
+        ] {
+            Mock::given(method("PUT"))
+                .and(query_param("partNumber", part))
+                .and(query_param("total", total))
+                .and(query_param("chunks", chunks))
+                .respond_with(ResponseTemplate::new(200).insert_header("ETag", etag))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let session = session(&server, std::path::Path::new("live.flv"));
+        let upos = Upos::from(session.in_flight.as_ref().unwrap(), &retry(1))?;
+        let source =
+            futures::stream::iter(["012", "3456", "789"]).map(|s| Ok(bytes::Bytes::from(s)));
+        let progress = Progress::default();
+        let file_progress = progress.file("live.flv", 0);
+        assert_eq!(
+            upos.upload_stream(source, 16, 2, &file_progress).await?,
+            [
+                (0, "e1".to_string()),
+                (1, "e2".to_string()),
+                (2, "e3".to_string())
+            ]
+        );
+        assert_eq!(file_progress.finish("n1").total_bytes, 10);
+        Ok(())
+    }
+}
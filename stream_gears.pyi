@@ -0,0 +1,353 @@
+import os
+from typing import Any, Awaitable, Callable, Dict, List, Mapping, Optional, Sequence, Tuple, Union
+
+_Path = Union[str, os.PathLike]
+_SegmentLike = Union["Segment", Mapping[str, Any], Any]
+_Meta = Union["StudioMeta", Mapping[str, Any]]
+
+class StreamGearsError(Exception):
+    status: Optional[int]
+    url: Optional[str]
+    path: Optional[str]
+
+class NetworkError(StreamGearsError): ...
+class AuthError(StreamGearsError): ...
+
+class LoginError(AuthError):
+    code: int
+    message: str
+
+class CaptchaRequiredError(LoginError):
+    recaptcha_token: str
+    gee_gt: str
+    gee_challenge: str
+
+class StreamEndedError(StreamGearsError): ...
+class ParseError(StreamGearsError): ...
+class DiskError(StreamGearsError): ...
+class UploadError(StreamGearsError): ...
+
+class Segment:
+    """A rule for splitting a recording into files.
+
+    Wherever a segment is expected, a dict or object with the keys or attributes `time`
+    and/or `size`, `cron`, `meta_change` or `any` is accepted as well.
+    """
+
+    @staticmethod
+    def time(secs: int) -> Segment: ...
+    @staticmethod
+    def size(bytes: int) -> Segment: ...
+    @staticmethod
+    def cron(expr: str) -> Segment: ...
+    @staticmethod
+    def meta_change() -> Segment: ...
+    @staticmethod
+    def any(*segments: _SegmentLike) -> Segment: ...
+
+class UploadLine:
+    Bda2: UploadLine
+    Ws: UploadLine
+    Qn: UploadLine
+    Kodo: UploadLine
+    Cos: UploadLine
+    CosInternal: UploadLine
+
+class Snapshot:
+    bytes_written: int
+    file_bytes: int
+    duration_ms: int
+    audio_tags: int
+    video_tags: int
+    script_tags: int
+    non_monotonic_timestamps: int
+    files: int
+    file_name: Optional[str]
+    room: Optional[str]
+    title: Optional[str]
+    video_codec: Optional[str]
+    video_profile: Optional[int]
+    video_level: Optional[int]
+    width: Optional[int]
+    height: Optional[int]
+    audio_codec: Optional[str]
+    sample_rate: Optional[int]
+    channels: Optional[int]
+    elapsed_secs: float
+    throughput: float
+    bitrate_kbps: float
+
+class Downloader:
+    def __init__(
+        self,
+        url: str,
+        header_map: Dict[str, str],
+        file_name: str,
+        segment: Optional[_SegmentLike] = None,
+        room: Optional[str] = None,
+        min_free_space: int = 0,
+        check_interval: int = 10,
+        quota: Optional[int] = None,
+        quota_policy: str = "stop",
+    ) -> None: ...
+    def run(self) -> None: ...
+    def run_async(self) -> Awaitable[None]: ...
+    def stop(self) -> None: ...
+    def stats(self) -> Snapshot: ...
+    def set_title(self, title: str) -> None: ...
+
+class StudioMeta:
+    title: str
+    tid: int
+    tag: str
+    copyright: int
+    source: str
+    desc: str
+    dynamic: str
+    cover: str
+    dtime: Optional[int]
+    part_titles: List[str]
+    subtitle_open: bool
+    subtitle_lang: str
+    no_reprint: bool
+    open_elec: bool
+    mission_id: Optional[int]
+    topic_id: Optional[int]
+    dolby: bool
+    up_selection_reply: bool
+    up_close_reply: bool
+    up_close_danmu: bool
+    def __init__(
+        self,
+        title: str,
+        tid: int = 171,
+        tag: str = "",
+        copyright: int = 1,
+        source: str = "",
+        desc: str = "",
+        dynamic: str = "",
+        cover: str = "",
+        dtime: Optional[int] = None,
+        part_titles: Sequence[str] = (),
+        subtitle_open: bool = False,
+        subtitle_lang: str = "",
+        no_reprint: bool = False,
+        open_elec: bool = False,
+        mission_id: Optional[int] = None,
+        topic_id: Optional[int] = None,
+        dolby: bool = False,
+        up_selection_reply: bool = False,
+        up_close_reply: bool = False,
+        up_close_danmu: bool = False,
+    ) -> None: ...
+    def validate(self, parts: Optional[int] = None) -> None: ...
+
+class Summary:
+    file_name: str
+    total_bytes: int
+    uploaded_bytes: int
+    elapsed_secs: float
+    throughput: float
+    server_file_name: str
+
+class LineProbe:
+    line: UploadLine
+    latency_ms: Optional[float]
+    throughput: Optional[float]
+    error: Optional[str]
+
+class Submission:
+    aid: Optional[int]
+    title: str
+    desc: str
+    tag: str
+    tid: int
+    cover: str
+    @staticmethod
+    def load(cookie_file: _Path, vid: str) -> Submission: ...
+    def edit(
+        self,
+        title: Optional[str] = None,
+        desc: Optional[str] = None,
+        tag: Optional[str] = None,
+        tid: Optional[int] = None,
+        cover: Optional[str] = None,
+        dynamic: Optional[str] = None,
+        source: Optional[str] = None,
+        copyright: Optional[int] = None,
+    ) -> None: ...
+    def parts(self) -> List[Tuple[Optional[str], str]]: ...
+    def remove_part(self, index: int) -> None: ...
+    def set_part_title(self, index: int, title: str) -> None: ...
+    def append(
+        self,
+        video_path: Sequence[_Path],
+        line: Optional[UploadLine] = None,
+        limit: int = 3,
+        part_titles: Sequence[str] = (),
+        state_file: Optional[_Path] = None,
+    ) -> None: ...
+    def submit(self) -> str: ...
+
+class Job:
+    id: str
+    video_path: List[str]
+    cookie_file: str
+    meta: StudioMeta
+    line: Optional[UploadLine]
+    limit: int
+    status: str
+    attempts: int
+    error: Optional[str]
+    response: Optional[str]
+    created: int
+    updated: int
+    retry_at: int
+
+class UploadQueue:
+    running: bool
+    def __init__(self, dir: _Path) -> None: ...
+    def enqueue(
+        self,
+        video_path: Sequence[_Path],
+        cookie_file: _Path,
+        meta: _Meta,
+        line: Optional[UploadLine] = None,
+        limit: int = 3,
+    ) -> str: ...
+    def status(self, id: str) -> Optional[Job]: ...
+    def jobs(self) -> List[Job]: ...
+    def retry(self, id: str) -> None: ...
+    def remove(self, id: str) -> None: ...
+    def start(self, workers: int = 1, max_attempts: int = 3) -> None: ...
+    def stop(self) -> None: ...
+
+class LiveUpload:
+    done: bool
+    def __init__(
+        self,
+        path: _Path,
+        cookie_file: _Path,
+        meta: _Meta,
+        line: Optional[UploadLine] = None,
+        limit: int = 3,
+        poll_interval: float = 1.0,
+        size_hint: Optional[int] = None,
+    ) -> None: ...
+    def finish(self) -> None: ...
+    def wait(self) -> str: ...
+
+class Credentials:
+    path: str
+    mid: int
+    uname: Optional[str]
+    expires: Optional[int]
+    platform: Optional[str]
+    token_expires: Optional[int]
+    refreshed: bool
+
+class QrLogin:
+    path: str
+    status: str
+    url: Optional[str]
+    def __init__(self, path: _Path = "cookies.json") -> None: ...
+    def start(self) -> Tuple[str, List[List[bool]]]: ...
+    def poll(self) -> str: ...
+    def render(self) -> str: ...
+
+class Account:
+    name: str
+    mid: int
+    uname: Optional[str]
+    platform: Optional[str]
+    expires: Optional[int]
+    added: int
+    path: str
+    default: bool
+
+class AccountStore:
+    dir: str
+    def __init__(self, dir: Optional[_Path] = None) -> None: ...
+    def path(self, name: str) -> str: ...
+    def import_cookies(self, name: str, cookie_file: _Path) -> Account: ...
+    def qr_login(self, name: str) -> QrLogin: ...
+    def login_by_sms(self, name: str, code: int, ret: str) -> Account: ...
+    def register(self, name: str) -> Account: ...
+    def list(self) -> List[Account]: ...
+    def get(self, name: str) -> Optional[Account]: ...
+    def remove(self, name: str) -> None: ...
+    def default(self, name: Optional[str] = None) -> Optional[Account]: ...
+
+def configure_logging(
+    level: str = "info",
+    path: Optional[_Path] = None,
+    rotation: str = "never",
+    json: bool = False,
+    stdout: bool = True,
+    quiet: bool = False,
+    python: bool = False,
+) -> None: ...
+def download(
+    url: str,
+    header_map: Dict[str, str],
+    file_name: str,
+    segment: Optional[_SegmentLike] = None,
+) -> None: ...
+def download_async(
+    url: str,
+    header_map: Dict[str, str],
+    file_name: str,
+    segment: Optional[_SegmentLike] = None,
+) -> Awaitable[None]: ...
+def recover(dir: _Path, rewrite_metadata: bool = True) -> List[str]: ...
+def extract_keyframe(flv: _Path, output: _Path) -> int: ...
+def upload(
+    video_path: Sequence[_Path],
+    cookie_file: Optional[_Path],
+    meta: _Meta,
+    line: Optional[UploadLine] = None,
+    limit: int = 3,
+    state_file: Optional[_Path] = None,
+    max_attempts: int = 5,
+    retry_backoff: float = 1.0,
+    on_progress: Optional[Callable[[str, int, int], None]] = None,
+    on_complete: Optional[Callable[[Summary], None]] = None,
+    progress_interval: float = 1.0,
+    account: Optional[str] = None,
+    accounts_dir: Optional[_Path] = None,
+) -> None: ...
+def upload_async(
+    video_path: Sequence[_Path],
+    cookie_file: Optional[_Path],
+    meta: _Meta,
+    line: Optional[UploadLine] = None,
+    limit: int = 3,
+    state_file: Optional[_Path] = None,
+    max_attempts: int = 5,
+    retry_backoff: float = 1.0,
+    on_progress: Optional[Callable[[str, int, int], None]] = None,
+    on_complete: Optional[Callable[[Summary], None]] = None,
+    progress_interval: float = 1.0,
+    account: Optional[str] = None,
+    accounts_dir: Optional[_Path] = None,
+) -> Awaitable[None]: ...
+def set_upload_rate_limit(bytes_per_sec: Optional[int] = None) -> None: ...
+def get_upload_rate_limit() -> Optional[int]: ...
+def probe_lines() -> List[LineProbe]: ...
+def set_line_cache_ttl(secs: float) -> None: ...
+def login_by_cookies(path: _Path = "cookies.json") -> bool: ...
+def login_by_cookies_async(path: _Path = "cookies.json") -> Awaitable[bool]: ...
+def send_sms(country_code: int, phone: int, captcha: Optional[Dict[str, str]] = None) -> str: ...
+def send_sms_async(
+    country_code: int, phone: int, captcha: Optional[Dict[str, str]] = None
+) -> Awaitable[str]: ...
+def login_by_sms(code: int, ret: str, path: _Path = "cookies.json") -> bool: ...
+def login_by_sms_async(code: int, ret: str, path: _Path = "cookies.json") -> Awaitable[bool]: ...
+def get_qrcode() -> str: ...
+def get_qrcode_async() -> Awaitable[str]: ...
+def login_by_qrcode(ret: str, path: _Path = "cookies.json") -> bool: ...
+def login_by_qrcode_async(ret: str, path: _Path = "cookies.json") -> Awaitable[bool]: ...
+def load_credentials(path: _Path = "cookies.json") -> Credentials: ...
+def load_credentials_async(path: _Path = "cookies.json") -> Awaitable[Credentials]: ...
+def refresh_credentials(path: _Path = "cookies.json") -> Credentials: ...
+def refresh_credentials_async(path: _Path = "cookies.json") -> Awaitable[Credentials]: ...
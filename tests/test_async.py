@@ -0,0 +1,26 @@
+import asyncio
+
+import stream_gears
+
+
+async def main():
+    segment = {"time": 60 * 60}
+    recordings = [
+        stream_gears.download_async(
+            url,
+            {"referer": "https://live.bilibili.com"},
+            f"room{i}%Y-%m-%dT%H_%M_%S",
+            segment,
+        )
+        for i, url in enumerate(["", ""])
+    ]
+    upload = stream_gears.upload_async(
+        ["examples/test.mp4"],
+        "cookies.json",
+        {"title": "title", "tid": 171, "tag": "tag", "copyright": 1, "source": "source"},
+    )
+    await asyncio.gather(*recordings, upload)
+
+
+if __name__ == '__main__':
+    asyncio.run(main())
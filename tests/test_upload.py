@@ -1,18 +1,21 @@
 import stream_gears
 
 if __name__ == '__main__':
+    meta = stream_gears.StudioMeta(
+        "title",
+        tid=171,
+        tag="tag",
+        copyright=1,
+        source="source",
+        desc="desc",
+        dynamic="dynamic",
+        part_titles=["P1"],
+    )
     stream_gears.upload(
         ["examples/test.mp4"],
         "cookies.json",
-        "title",
-        171,
-        "tag",
-        1,
-        "source",
-        "desc",
-        "dynamic",
-        "",
-        None,
-        stream_gears.UploadLine.Bda2,
-        3,
+        meta,
+        line=stream_gears.UploadLine.Bda2,
+        limit=3,
+        state_file="upload-state.json",
     )